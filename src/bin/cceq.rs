@@ -3,8 +3,6 @@ use std::iter::FromIterator;
 use std::path::Path;
 use std::process::exit;
 
-#[macro_use]
-extern crate serde_derive;
 extern crate serde_json;
 use serde_json::Result;
 
@@ -15,6 +13,9 @@ use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 
+extern crate rstrace;
+use rstrace::CompileCmd;
+
 #[derive(Debug)]
 struct CompareError {
     details: String,
@@ -40,12 +41,14 @@ impl Error for CompareError {
     }
 }
 
-include!("../tools/ccmd.rs");
-
 fn read_json(filename: &Path) -> Result<Vec<CompileCmd>> {
     let contents = read_to_string(filename).expect("Something went wrong reading the file");
 
-    serde_json::from_str(&contents)
+    let mut cmds: Vec<CompileCmd> = serde_json::from_str(&contents)?;
+    for cmd in &mut cmds {
+        cmd.normalize();
+    }
+    Ok(cmds)
 }
 
 fn compare_cmds(
@@ -0,0 +1,114 @@
+//! The errors of tracing a build and writing its outputs.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum Error {
+    /// strace isn't in `PATH` or doesn't behave like strace.
+    StraceNotFound(String),
+    /// strace couldn't be started or waited for.
+    Strace(io::Error),
+    /// The traced build exited with this code, or was killed by a signal
+    /// if that is None.
+    BuildFailed(Option<i32>),
+    /// An input, such as an strace log or a compilation database,
+    /// couldn't be read.
+    Input { path: PathBuf, error: io::Error },
+    /// An input is malformed.
+    Parse { path: PathBuf, message: String },
+    /// An output couldn't be serialized or written.
+    Output { path: PathBuf, error: io::Error },
+    /// Entries of a compilation database failed to compile.
+    VerifyFailed { failed: usize, total: usize },
+    /// An invalid command line option.
+    Usage(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The exit code of `rstrace` for the error. These follow
+    /// `sysexits.h`, except that a failed build exits with its own code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::BuildFailed(code) => code.unwrap_or(1),
+            Error::VerifyFailed { .. } => 1,
+            Error::Usage(_) => 64,
+            Error::Parse { .. } => 65,
+            Error::Input { .. } => 66,
+            Error::StraceNotFound(_) => 69,
+            Error::Strace(_) => 71,
+            Error::Output { .. } => 73,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::StraceNotFound(msg) => write!(f, "{}; install strace or add it to PATH", msg),
+            Error::Strace(e) => write!(f, "couldn't run strace: {}", e),
+            Error::BuildFailed(Some(code)) => write!(f, "the build failed with exit code {}", code),
+            Error::BuildFailed(None) => write!(f, "the build was killed by a signal"),
+            Error::Input { path, error } => {
+                write!(f, "couldn't read {}: {}", path.display(), error)
+            }
+            Error::Parse { path, message } => {
+                write!(f, "couldn't parse {}: {}", path.display(), message)
+            }
+            Error::Output { path, error } => write!(
+                f,
+                "couldn't write {}: {}; check that the directory is writable",
+                path.display(),
+                error
+            ),
+            Error::VerifyFailed { failed, total } => {
+                write!(f, "{} of {} entries failed to compile", failed, total)
+            }
+            Error::Usage(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Writes `contents` to the file `path`.
+pub fn write_output(path: &str, contents: &[u8]) -> Result<()> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|error| Error::Output {
+            path: PathBuf::from(path),
+            error,
+        })
+}
+
+/// Writes `value` to the file `path` as pretty-printed JSON.
+pub fn write_json<T: Serialize + ?Sized>(path: &str, value: &T) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| Error::Output {
+        path: PathBuf::from(path),
+        error: e.into(),
+    })?;
+    write_output(path, json.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.json");
+        write_json(path.to_str().unwrap(), &["a"]).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[\n  \"a\"\n]");
+
+        let missing = dir.path().join("missing/out.json");
+        let err = write_output(missing.to_str().unwrap(), b"").unwrap_err();
+        assert_eq!(err.exit_code(), 73);
+        assert!(err.to_string().starts_with("couldn't write "));
+    }
+}
@@ -0,0 +1,52 @@
+//! Traces C/C++ builds with strace and recovers the compiler, linker and
+//! archiver invocations they ran.
+//!
+//! ```no_run
+//! use rstrace::Tracer;
+//!
+//! let tree = Tracer::new(&["make", "-j8"]).trace().unwrap();
+//! for exec in tree.execs() {
+//!     println!("{:?} {:?}", rstrace::ToolKind::from(exec), exec.args);
+//! }
+//! ```
+#[macro_use]
+extern crate nom;
+
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+
+extern crate tempfile;
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+pub mod error;
+pub mod parser;
+pub mod process;
+pub mod sink;
+pub mod tools;
+mod trace;
+
+pub use crate::error::Error;
+pub use crate::parser::Event;
+pub use crate::process::ProcessTree;
+pub use crate::sink::EventSink;
+pub use crate::tools::ccmd::CompileCmd;
+pub use crate::tools::ToolKind;
+pub use crate::trace::{process_output_file, Tracer};
+
+/// A single `execve` entry in an strace log
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exec {
+    /// The process making the call, taken from the name of its strace log.
+    pub pid: u32,
+    pub path: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub retcode: u8,
+    /// Seconds since the epoch at which the call was made.
+    pub timestamp: Option<f64>,
+}
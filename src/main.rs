@@ -1,165 +1,483 @@
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::process::exit;
 
-#[macro_use]
-extern crate nom;
-#[macro_use(crate_version, crate_authors)]
+#[macro_use(crate_version, crate_authors, value_t)]
 extern crate clap;
-use clap::{App, AppSettings, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
+extern crate rstrace;
+use rstrace::tools::ar::write_archives;
+use rstrace::tools::builtins::BuiltinsMode;
+use rstrace::tools::cc::{filter_execs, write_compile_commands, Options, DEFAULT_ENVIRONMENT};
+use rstrace::tools::dot::write_dot;
+use rstrace::tools::flags::{FilterPolicy, FlagRule, Profile};
+use rstrace::tools::graph::{write_build_graph, BuildGraph, Node};
+use rstrace::tools::ld::write_link_commands;
+use rstrace::tools::modules::write_modules;
+use rstrace::tools::paths::{clean, glob, PathOptions};
+use rstrace::tools::probes::{is_probe, ProbeOptions};
+use rstrace::tools::report::print_report;
+use rstrace::tools::timeline::write_timeline;
+use rstrace::tools::verify::{read_compile_commands, verify, VerifyMode};
+use rstrace::{Error, Exec, ProcessTree, ToolKind, Tracer};
 
-extern crate tempfile;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use tempfile::tempdir;
-
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_json;
-
-mod tools;
-use tools::cc::{filter_execs, write_compile_commands};
-
-mod parser;
-
-/// A single `execve` entry in an strace log
-#[derive(Debug, PartialEq)]
-pub struct Exec {
-    pub path: String,
-    pub args: Vec<String>,
-    pub env: Vec<(String, String)>,
-    pub retcode: u8,
+/// Traces the build command given by `args`.
+fn tracer(args: &ArgMatches) -> Tracer {
+    Tracer::new(args.values_of("cmd").unwrap())
 }
 
-fn locate_strace() -> Result<String, &'static str> {
-    // get path to strace
-    let mut which_strace = Command::new("which");
-    let mut which_output = which_strace
-        .arg("strace")
-        .output()
-        .expect("strace is not in path");
-    assert!(which_output.status.success());
-
-    // drop the trailing newline
-    assert_eq!(Some('\n' as u8), which_output.stdout.pop());
-    let strace_path = String::from_utf8_lossy(&which_output.stdout);
-
-    // check that strace -V produces sane output
-    let strace_ver_output = Command::new(strace_path.to_string())
-        .arg("-V")
-        .output()
-        .expect("could not get strace version");
-    assert!(strace_ver_output.status.success());
-    let strace_ver = String::from_utf8_lossy(&strace_ver_output.stdout[0..17]);
-    assert_eq!("strace -- version", strace_ver);
-
-    Ok(strace_path.to_string())
+/// Runs the build, warning about the programs missing from the trace.
+fn trace(tracer: Tracer) -> Result<ProcessTree, Error> {
+    let tree = tracer.trace()?;
+    for (pid, line) in tree.unparsed() {
+        eprintln!(
+            "warning: couldn't parse a program run by process {}: {}",
+            pid, line
+        );
+    }
+    Ok(tree)
 }
 
-fn process_output_file<O>(
-    file: &PathBuf,
-    callback: fn(Exec) -> Option<O>,
-) -> Result<Vec<O>, String> {
-    let f = File::open(file).unwrap();
-    let buf = BufReader::new(&f);
-    let res = buf
-        .lines()
-        .filter_map(|l| {
-            parser::parseln(&l.unwrap())
-                .unwrap_or(None)
-                .and_then(|f| callback(f))
-        })
-        .collect::<Vec<O>>();
-
-    Ok(res)
+/// The compiler, linker and archiver invocations of the build.
+fn tool_execs(tree: &ProcessTree) -> Vec<(Exec, ToolKind)> {
+    tree.execs().cloned().filter_map(filter_execs).collect()
 }
 
-fn run_strace<O>(
-    args: ArgMatches,
-    output_file: &str,
-    callback: fn(Exec) -> Option<O>,
-) -> Result<Vec<O>, String> {
-    let strace_args = vec![
-        "-o",
-        output_file, // output to `$output_file.$pid`
-        "-ff",       // follow forks
-        "-e",
-        "trace=execve", // only trace execve calls
-        "-s",
-        "8192", // set max string length
-        "-v",   // request unabridged output
-    ];
-    // get the build command
-    let cmd: Vec<&str> = args.values_of("cmd").unwrap().collect();
-    let strace_path = locate_strace()?;
-
-    let mut strace_child = Command::new(strace_path)
-        .args(strace_args)
-        .args(cmd)
-        .spawn()
-        .expect("failed to run strace");
-
-    let output = strace_child
-        .wait()
-        .expect("couldn't get strace exit status");
-    if !output.success() {
-        exit(output.code().unwrap());
+fn run_graph(args: &ArgMatches) -> Result<(), Error> {
+    let tree = trace(tracer(args))?;
+    let graph = BuildGraph::from(&tool_execs(&tree));
+    write_build_graph(&graph)?;
+    if args.is_present("dot") {
+        write_dot(&tree, &graph, args.is_present("collapse-processes"))?;
+    }
+    if args.is_present("modules") {
+        write_modules(&graph)?;
     }
 
-    let tmp_dir = Path::new(output_file).parent().unwrap();
-    let tmp_files = tmp_dir
-        .read_dir()
-        .unwrap()
-        .map(|rde| {
-            if let Ok(entry) = rde {
-                if let Ok(file_type) = entry.file_type() {
-                    assert!(file_type.is_file());
-                    return entry.path();
-                }
+    let print_query = |path: &str, query: fn(&BuildGraph, usize) -> Vec<&Node>| {
+        let ids = graph.find(path);
+        if ids.is_empty() {
+            eprintln!("warning: {} is not part of the build graph", path);
+        }
+        for id in ids {
+            for node in query(&graph, id) {
+                println!("{}", node.path);
             }
-            // shouldn't happen since we strace to a pristine tempdir
-            panic!("unexpected non-file entry in {}", tmp_dir.to_str().unwrap());
-        })
-        .collect::<Vec<PathBuf>>();
-    let res = tmp_files
-        .iter()
-        .map(|file| process_output_file(file, callback).unwrap())
-        .flatten()
-        .collect::<Vec<O>>();
+        }
+    };
+    for source in args.values_of("binaries-containing").into_iter().flatten() {
+        print_query(source, BuildGraph::binaries_containing);
+    }
+    for artifact in args.values_of("sources-of").into_iter().flatten() {
+        print_query(artifact, BuildGraph::sources_of);
+    }
+    Ok(())
+}
 
-    Ok(res)
+fn run_verify(args: &ArgMatches) -> Result<(), Error> {
+    let mode = value_t!(args, "mode", VerifyMode).unwrap_or_else(|e| e.exit());
+    let jobs = match args.value_of("jobs") {
+        Some(_) => value_t!(args, "jobs", usize).unwrap_or_else(|e| e.exit()),
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let cmds = read_compile_commands(Path::new(args.value_of("database").unwrap()))?;
+    let failures = verify(&cmds, mode, jobs)?;
+    for f in &failures {
+        eprintln!("failed: {} (in {})", f.file, f.directory);
+        for line in f.output.lines() {
+            eprintln!("    {}", line);
+        }
+    }
+    if !failures.is_empty() {
+        return Err(Error::VerifyFailed {
+            failed: failures.len(),
+            total: cmds.len(),
+        });
+    }
+    println!("all {} entries compiled", cmds.len());
+    Ok(())
 }
 
-fn run_app() -> Result<(), String> {
+fn run_app() -> Result<(), Error> {
     if !cfg!(unix) {
-        return Err("rstrace only runs on Unix hosts".to_string());
+        return Err(Error::Usage("rstrace only runs on Unix hosts".to_string()));
     }
 
     let matches = App::new("rstrace")
         .version(crate_version!())
         .author(crate_authors!(", "))
         .about("traces C/C++ compiler and linker invocations")
+        .after_help(
+            "EXIT STATUS:\n\
+             that of the build if it fails, 1 if verify finds failing entries, 64 for invalid \
+             options, 65 for malformed inputs, 66 for unreadable inputs, 69 if strace is missing, \
+             71 if strace can't be run and 73 if an output can't be written",
+        )
         .setting(AppSettings::TrailingVarArg)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("graph")
+                .about("writes the dependency graph of build artifacts to build_graph.json")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("binaries-containing")
+                        .long("binaries-containing")
+                        .value_name("SOURCE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("print the executables and shared libraries built from SOURCE"),
+                )
+                .arg(
+                    Arg::with_name("sources-of")
+                        .long("sources-of")
+                        .value_name("ARTIFACT")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("print the sources compiled into ARTIFACT"),
+                )
+                .arg(
+                    Arg::with_name("dot")
+                        .long("dot")
+                        .help("also write the process tree and build graph to build_graph.dot"),
+                )
+                .arg(
+                    Arg::with_name("collapse-processes")
+                        .long("collapse-processes")
+                        .requires("dot")
+                        .help("merge the processes running the same program in the DOT graph"),
+                )
+                .arg(Arg::with_name("modules").long("modules").help(
+                    "also write the products and entry points of each source to modules.json",
+                ))
+                .arg(Arg::from_usage("<cmd>... 'build command'")),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("prints the slowest compiles, parallelism and critical path of a build")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("top")
+                        .long("top")
+                        .value_name("N")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("number of compiles and directories to list"),
+                )
+                .arg(Arg::from_usage("<cmd>... 'build command'")),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("re-runs the entries of a compilation database and reports those that fail")
+                .arg(
+                    Arg::with_name("mode")
+                        .long("mode")
+                        .takes_value(true)
+                        .possible_values(VerifyMode::NAMES)
+                        .default_value("syntax-only")
+                        .help("check syntax only, or compile to a scratch directory"),
+                )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .takes_value(true)
+                        .help("number of entries to run at once [default: number of CPUs]"),
+                )
+                .arg(
+                    Arg::with_name("database")
+                        .default_value("compile_commands.json")
+                        .help("compilation database to verify"),
+                ),
+        )
+        .arg(
+            Arg::with_name("assembly")
+                .long("assembly")
+                .help("include assembly sources and assembler invocations"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .possible_values(Profile::NAMES)
+                .default_value("intercept-build")
+                .help("flag filtering profile"),
+        )
+        .arg(
+            Arg::with_name("strip-flag")
+                .long("strip-flag")
+                .value_name("FLAG[:N]")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help("also drop FLAG and N operands; a trailing * matches a prefix"),
+        )
+        .arg(
+            Arg::with_name("keep-flag")
+                .long("keep-flag")
+                .value_name("FLAG")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .help("keep FLAG even if the profile drops it"),
+        )
+        .arg(
+            Arg::with_name("command")
+                .long("command")
+                .help("emit each entry as a shell-escaped command string"),
+        )
+        .arg(
+            Arg::with_name("record-output")
+                .long("record-output")
+                .help("record the output file of each compilation"),
+        )
+        .arg(
+            Arg::with_name("absolute-paths")
+                .long("absolute-paths")
+                .help("make recorded paths absolute"),
+        )
+        .arg(
+            Arg::with_name("resolve-symlinks")
+                .long("resolve-symlinks")
+                .help("resolve symbolic links in recorded paths"),
+        )
+        .arg(
+            Arg::with_name("clean-paths")
+                .long("clean-paths")
+                .help("lexically remove . and .. from recorded paths"),
+        )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("replace DIR in absolute paths with a placeholder"),
+        )
+        .arg(
+            Arg::with_name("root-placeholder")
+                .long("root-placeholder")
+                .value_name("STR")
+                .takes_value(true)
+                .default_value("${ROOT}")
+                .help("placeholder used for --root"),
+        )
+        .arg(
+            Arg::with_name("keep-duplicates")
+                .long("keep-duplicates")
+                .help("keep repeated compilations, in execution order"),
+        )
+        .arg(
+            Arg::with_name("environment")
+                .long("environment")
+                .help("record compiler-relevant environment variables with each entry"),
+        )
+        .arg(
+            Arg::with_name("environment-var")
+                .long("environment-var")
+                .value_name("VAR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("also record VAR; a trailing * matches a prefix. Implies --environment"),
+        )
+        .arg(
+            Arg::with_name("headers")
+                .long("headers")
+                .help("record the headers read by each compilation"),
+        )
+        .arg(
+            Arg::with_name("generated")
+                .long("generated")
+                .help("record which sources and headers of each compilation the build generated"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("only record compilations whose source or directory matches GLOB, like src/**; relative globs start at --root or the current directory"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("don't record compilations whose source or directory matches GLOB, like third_party/**"),
+        )
+        .arg(
+            Arg::with_name("keep-probes")
+                .long("keep-probes")
+                .help("keep compilations that look like configure checks or compiler probes"),
+        )
+        .arg(
+            Arg::with_name("probe")
+                .long("probe")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("also drop tools run on sources or in directories matching GLOB; relative globs start at --root or the current directory"),
+        )
+        .arg(
+            Arg::with_name("not-probe")
+                .long("not-probe")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("never drop tools run on sources or in directories matching GLOB"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .long("target")
+                .help("record the target triple and configuration of each compilation"),
+        )
+        .arg(
+            Arg::with_name("only-target")
+                .long("only-target")
+                .value_name("KEY")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("only keep compilations for KEY, a target triple like arm-none-eabi or a full configuration recorded by --target"),
+        )
+        .arg(
+            Arg::with_name("builtins")
+                .long("builtins")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(BuiltinsMode::NAMES)
+                .help("query compilers for built-in include paths and target; inject them into entries or write compiler_builtins.json"),
+        )
+        .arg(
+            Arg::with_name("link-commands")
+                .long("link-commands")
+                .help("also write link steps to link_commands.json"),
+        )
+        .arg(
+            Arg::with_name("archives")
+                .long("archives")
+                .help("also write archive members to archives.json"),
+        )
+        .arg(
+            Arg::with_name("timeline")
+                .long("timeline")
+                .help("also write a Chrome trace of the tools run by the build to trace.json"),
+        )
         .arg(Arg::from_usage("<cmd>... 'build command'"))
         .get_matches();
 
-    {
-        // Create a directory inside of `std::env::temp_dir()`
-        let tmp_dir = tempdir().map_err(|e| format!("{}", e))?;
-        let strace_outfile = tmp_dir.path().join("rstrace.out");
-        let strace_outfile = strace_outfile
-            .to_str()
-            .expect("failed to construct temporary output filename");
+    match matches.subcommand() {
+        ("graph", Some(sub)) => return run_graph(sub),
+        ("verify", Some(sub)) => return run_verify(sub),
+        ("report", Some(sub)) => {
+            let top = value_t!(sub, "top", usize).unwrap_or_else(|e| e.exit());
+            print_report(&trace(tracer(sub))?, top);
+            return Ok(());
+        }
+        _ => {}
+    }
 
-        let execs = run_strace(matches, strace_outfile, filter_execs)?;
-        write_compile_commands(execs).expect("failed to write compile commands");
+    let profile = value_t!(matches, "profile", Profile).unwrap_or_else(|e| e.exit());
+    let mut flags = FilterPolicy::new(profile);
+    for rule in matches.values_of("strip-flag").into_iter().flatten() {
+        flags.strip(rule.parse::<FlagRule>().map_err(Error::Usage)?);
+    }
+    for flag in matches.values_of("keep-flag").into_iter().flatten() {
+        flags.keep(flag);
+    }
+    let mut environment = vec![];
+    if matches.is_present("environment") || matches.is_present("environment-var") {
+        environment.extend(DEFAULT_ENVIRONMENT.iter().map(|v| v.to_string()));
+    }
+    environment.extend(
+        matches
+            .values_of("environment-var")
+            .into_iter()
+            .flatten()
+            .map(|v| v.to_owned()),
+    );
+    // relative globs are anchored at the top of the build
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let root = match matches.value_of("root") {
+        Some(dir) => clean(&cwd.join(dir)),
+        None => cwd,
+    };
+    let globs = |name| {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(|g| glob(g, &root))
+            .collect()
+    };
+    let opts = Options {
+        assembly: matches.is_present("assembly"),
+        flags,
+        command: matches.is_present("command"),
+        output: matches.is_present("record-output"),
+        keep_duplicates: matches.is_present("keep-duplicates"),
+        environment,
+        headers: matches.is_present("headers"),
+        generated: matches.is_present("generated"),
+        builtins: matches
+            .value_of("builtins")
+            .map(|_| value_t!(matches, "builtins", BuiltinsMode).unwrap_or_else(|e| e.exit())),
+        target: matches.is_present("target"),
+        targets: matches
+            .values_of("only-target")
+            .into_iter()
+            .flatten()
+            .map(|t| t.to_owned())
+            .collect(),
+        include: globs("include"),
+        exclude: globs("exclude"),
+        paths: PathOptions {
+            absolute: matches.is_present("absolute-paths"),
+            resolve_symlinks: matches.is_present("resolve-symlinks"),
+            clean: matches.is_present("clean-paths"),
+            root: matches.value_of("root").map(|dir| {
+                let placeholder = matches.value_of("root-placeholder").unwrap();
+                (PathBuf::from(dir), placeholder.to_owned())
+            }),
+        },
+    };
+    let probes = ProbeOptions {
+        keep: matches.is_present("keep-probes"),
+        probes: globs("probe"),
+        not_probes: globs("not-probe"),
+        build_dir: std::env::current_dir().ok(),
+    };
+    let link_commands = matches.is_present("link-commands");
+    let archives = matches.is_present("archives");
+    let timeline = matches.is_present("timeline");
 
-        // `tmp_dir` goes out of scope, the directory will be deleted here.
-        tmp_dir.close().map_err(|e| format!("{}", e))?;
+    let tree = trace(
+        tracer(&matches)
+            .opens(opts.headers || opts.generated)
+            .renames(opts.generated),
+    )?;
+    if timeline {
+        write_timeline(&tree)?;
+    }
+    let mut execs = tool_execs(&tree);
+    execs.retain(|(e, _)| !is_probe(e, &probes));
+    if link_commands {
+        write_link_commands(&execs)?;
+    }
+    if archives {
+        write_archives(&execs)?;
+    }
+    for warning in write_compile_commands(execs, &tree, &opts)? {
+        eprintln!("warning: {}", warning);
     }
 
     Ok(())
@@ -169,8 +487,8 @@ fn main() {
     exit(match run_app() {
         Ok(_) => 0,
         Err(err) => {
-            eprintln!("error: {:?}", err);
-            1
+            eprintln!("error: {}", err);
+            err.exit_code()
         }
     });
 }
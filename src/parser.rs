@@ -1,5 +1,7 @@
 use crate::Exec;
 use nom::types::CompleteStr;
+use nom::{ErrorKind, IResult};
+use regex::Regex;
 
 /// to combine nom parsing functions, they have to have
 /// compatible return types, so they all return `Expr`.
@@ -11,17 +13,89 @@ enum Expr {
     ArrOfKeyVal(Vec<(String, String)>),
 }
 
-named!(string<CompleteStr, &str>,
-    map!(
-        delimited!(char!('"'), is_not!("\""), char!('"')),
-        |cs| cs.0
-    )
-);
+// a string as printed by strace: quoted, with `\\`, `\"`, `\n` and the like
+// escaped, other special bytes in octal or hex, and a trailing `...` if it
+// was truncated
+fn string(input: CompleteStr) -> IResult<CompleteStr, String> {
+    let error = || {
+        Err(nom::Err::Error(error_position!(
+            input,
+            ErrorKind::Custom(0)
+        )))
+    };
+    if !input.starts_with('"') {
+        return error();
+    }
+    let mut bytes = vec![];
+    let mut rest = &input[1..];
+    loop {
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return error(),
+        };
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '"' => break,
+            '\\' => {
+                let e = match rest.chars().next() {
+                    Some(e) => e,
+                    None => return error(),
+                };
+                rest = &rest[e.len_utf8()..];
+                let byte = match e {
+                    'a' => 0x07,
+                    'b' => 0x08,
+                    't' => b'\t',
+                    'n' => b'\n',
+                    'v' => 0x0b,
+                    'f' => 0x0c,
+                    'r' => b'\r',
+                    'x' => {
+                        let len = rest
+                            .bytes()
+                            .take(2)
+                            .take_while(u8::is_ascii_hexdigit)
+                            .count();
+                        let byte = u8::from_str_radix(&rest[..len], 16).ok();
+                        rest = &rest[len..];
+                        match byte {
+                            Some(byte) => byte,
+                            None => return error(),
+                        }
+                    }
+                    '0'..='7' => {
+                        let len = rest
+                            .bytes()
+                            .take(2)
+                            .take_while(|b| (b'0'..=b'7').contains(b))
+                            .count();
+                        let digits = format!("{}{}", e, &rest[..len]);
+                        rest = &rest[len..];
+                        match u8::from_str_radix(&digits, 8) {
+                            Ok(byte) => byte,
+                            Err(_) => return error(),
+                        }
+                    }
+                    e if e.is_ascii() => e as u8,
+                    _ => return error(),
+                };
+                bytes.push(byte);
+            }
+            c => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    let rest = rest.strip_prefix("...").unwrap_or(rest);
+    Ok((
+        CompleteStr(rest),
+        String::from_utf8_lossy(&bytes).into_owned(),
+    ))
+}
+
 named!(string_expr<CompleteStr, Expr>,
-    map!(string, |s| Expr::Str(s.to_string()))
+    map!(string, Expr::Str)
 );
 
-named!(arr_of_str<CompleteStr, Vec<&str>>,
+named!(arr_of_str<CompleteStr, Vec<String>>,
     delimited!(
         char!('['),
         separated_list!(
@@ -35,11 +109,7 @@ named!(arr_of_str<CompleteStr, Vec<&str>>,
 named!(arr_of_str_expr<CompleteStr, Expr>,
     map!(
         arr_of_str,
-        |v| Expr::ArrOfStr(v
-            .iter()
-            .map(|s| String::from(*s))
-            .collect::<Vec<String>>()
-        )
+        Expr::ArrOfStr
     )
 );
 
@@ -56,16 +126,14 @@ named!(retcode<CompleteStr, Expr>,
   map_res!(dbg_dmp!(take_while!(is_digit)), from_dec)
 );
 
-named!(env_var<CompleteStr,(&str,&str)>,
-    do_parse!(
-                tag_s!("\"")                   >>
-        key:    take_until_and_consume!("=")  >>
-        value:  take_until_and_consume!("\"") >>
-        ((&key, &value))
-    )
+named!(env_var<CompleteStr, (String, String)>,
+    map!(string, |s| match s.find('=') {
+        Some(i) => (s[..i].to_owned(), s[i + 1..].to_owned()),
+        None => (s, String::new()),
+    })
 );
 
-named!(arr_of_env_var<CompleteStr, Vec<(&str, &str)>>,
+named!(arr_of_env_var<CompleteStr, Vec<(String, String)>>,
     delimited!(
         char!('['),
         separated_list!(
@@ -79,11 +147,7 @@ named!(arr_of_env_var<CompleteStr, Vec<(&str, &str)>>,
 named!(arr_of_env_var_expr<CompleteStr, Expr>,
     map!(
         arr_of_env_var,
-        |v| Expr::ArrOfKeyVal(v
-            .iter()
-            .map(|s| (String::from(s.0), String::from(s.1)))
-            .collect::<Vec<(String, String)>>()
-        )
+        Expr::ArrOfKeyVal
     )
 );
 
@@ -100,31 +164,264 @@ named!(execve<CompleteStr, Exec>,
         (
             if let (Expr::Str(path), Expr::ArrOfStr(args), Expr::ArrOfKeyVal(env), Expr::UInt(r)) =
                 (path, args, env, retc) {
-                Exec { path, args, env, retcode: r }
+                Exec { pid: 0, path, args, env, retcode: r, timestamp: None }
             } else { panic!() }
         )
     )
 );
 
+/// An event in the strace log of a single process.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Exec(Exec),
+    /// `clone`, `fork` or `vfork` created the process `child`. Threads are
+    /// not reported.
+    Fork {
+        child: u32,
+        timestamp: Option<f64>,
+    },
+    /// The process successfully opened `path`, for writing if `write` is
+    /// set, or looked it up with `stat`. Directories, and paths relative to
+    /// a directory other than the working directory, are not reported.
+    Open {
+        path: String,
+        write: bool,
+        timestamp: Option<f64>,
+    },
+    /// The process successfully renamed `from` to `to`. Renames relative
+    /// to a directory other than the working directory are not reported.
+    Rename {
+        from: String,
+        to: String,
+        timestamp: Option<f64>,
+    },
+    /// The process exited with `retcode`, or was killed by a signal if
+    /// that is None.
+    Exit {
+        retcode: Option<u8>,
+        timestamp: Option<f64>,
+    },
+}
+
+impl Event {
+    /// Seconds since the epoch at which the event happened.
+    pub fn timestamp(&self) -> Option<f64> {
+        match self {
+            Event::Exec(e) => e.timestamp,
+            Event::Fork { timestamp, .. }
+            | Event::Open { timestamp, .. }
+            | Event::Rename { timestamp, .. }
+            | Event::Exit { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
 named!(footer<CompleteStr, Expr>,
     delimited!(tag_s!("+++ exited with "), retcode, tag_s!(" +++"))
 );
 
-named!(line<CompleteStr, Option<Exec>>,
-    alt!(
-        map!(footer, |_| None) |
-        map!(execve, |e| Some(e))
+named!(killed<CompleteStr, CompleteStr>,
+    preceded!(tag!("+++ killed by "), take_until_and_consume!(" +++"))
+);
+
+fn from_pid(input: CompleteStr) -> Result<u32, std::num::ParseIntError> {
+    input.parse::<u32>()
+}
+
+// the rest of the arguments of a call, up to and including the `=` before
+// its result, which strace pads to align the results of short calls
+fn call_args(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    lazy_static! {
+        static ref END: Regex = Regex::new(r"\) += ").unwrap();
+    }
+    match END.find(&input) {
+        Some(m) => Ok((
+            CompleteStr(&input[m.end()..]),
+            CompleteStr(&input[..m.start()]),
+        )),
+        None => Err(nom::Err::Error(error_position!(
+            input,
+            ErrorKind::Custom(0)
+        ))),
+    }
+}
+
+// a process created by clone, clone3, fork or vfork; None for threads
+named!(fork<CompleteStr, Option<u32>>,
+    do_parse!(
+                alt!(tag!("clone(") | tag!("clone3(") | tag!("fork(") | tag!("vfork(")) >>
+        args:   call_args >>
+        child:  map_res!(take_while1!(is_digit), from_pid) >>
+        (if args.contains("CLONE_THREAD") { None } else { Some(child) })
+    )
+);
+
+// a file opened by open or openat; None if the path can't be resolved
+named!(open<CompleteStr, Option<(String, bool)>>,
+    do_parse!(
+        cwd:    alt!(
+                    map!(tag!("open("), |_| true) |
+                    map!(
+                        preceded!(tag!("openat("), take_until_and_consume!(", ")),
+                        |dirfd| dirfd.0 == "AT_FDCWD"
+                    )
+                ) >>
+        path:   string >>
+                tag!(", ") >>
+        flags:  call_args >>
+                take_while1!(is_digit) >>
+        (
+            if (cwd || path.starts_with('/')) && !flags.contains("O_DIRECTORY") {
+                let write = ["O_WRONLY", "O_RDWR", "O_CREAT"].iter().any(|f| flags.contains(f));
+                Some((path, write))
+            } else {
+                None
+            }
+        )
+    )
+);
+
+fn is_cwd(dirfd: CompleteStr) -> bool {
+    dirfd.0 == "AT_FDCWD"
+}
+
+// a file looked up by stat, lstat, newfstatat or statx; None if the path
+// can't be resolved or is a directory
+named!(stat<CompleteStr, Option<String>>,
+    do_parse!(
+        cwd:    alt!(
+                    map!(alt!(tag!("stat(") | tag!("lstat(")), |_| true) |
+                    map!(
+                        preceded!(
+                            alt!(tag!("newfstatat(") | tag!("statx(")),
+                            take_until_and_consume!(", ")
+                        ),
+                        is_cwd
+                    )
+                ) >>
+        path:   string >>
+        info:   call_args >>
+                tag!("0") >>
+        (
+            if (cwd || path.starts_with('/')) && !info.contains("S_IFDIR") {
+                Some(path)
+            } else {
+                None
+            }
+        )
+    )
+);
+
+// a file renamed by rename, renameat or renameat2; None if a path can't be resolved
+named!(rename<CompleteStr, Option<(String, String)>>,
+    do_parse!(
+        cwd:    alt!(
+                    map!(tag!("rename("), |_| true) |
+                    map!(
+                        preceded!(
+                            alt!(tag!("renameat2(") | tag!("renameat(")),
+                            take_until_and_consume!(", ")
+                        ),
+                        is_cwd
+                    )
+                ) >>
+        from:   string >>
+                tag!(", ") >>
+        to_cwd: opt!(terminated!(is_not!(",\""), tag!(", "))) >>
+        to:     string >>
+                call_args >>
+                tag!("0") >>
+        (
+            if (cwd || from.starts_with('/')) && (to_cwd.is_none_or(is_cwd) || to.starts_with('/')) {
+                Some((from, to))
+            } else {
+                None
+            }
+        )
     )
 );
 
-pub fn parseln(input: &str) -> Result<Option<Exec>, String> {
-    let res = line(CompleteStr(input)).map_err(|_| format!("failed to parse:\n {}", input))?;
+fn from_secs(input: CompleteStr) -> Result<f64, std::num::ParseFloatError> {
+    input.parse::<f64>()
+}
 
-    if let Some(exec) = res.1 {
-        return Ok(Some(exec));
+// seconds since the epoch printed by `strace -ttt`
+named!(timestamp<CompleteStr, f64>,
+    map_res!(
+        terminated!(
+            recognize!(tuple!(take_while1!(is_digit), char!('.'), take_while1!(is_digit))),
+            char!(' ')
+        ),
+        from_secs
+    )
+);
+
+named!(line<CompleteStr, Option<Event>>,
+    do_parse!(
+        ts:     opt!(timestamp) >>
+        event:  alt!(
+                    map!(footer, |r| match r {
+                        Expr::UInt(r) => Some(Event::Exit { retcode: Some(r), timestamp: ts }),
+                        _ => None,
+                    }) |
+                    map!(killed, |_| Some(Event::Exit { retcode: None, timestamp: ts })) |
+                    map!(fork, |c| c.map(|child| Event::Fork { child, timestamp: ts })) |
+                    map!(open, |o| o.map(|(path, write)| Event::Open { path, write, timestamp: ts })) |
+                    map!(stat, |s| s.map(|path| Event::Open { path, write: false, timestamp: ts })) |
+                    map!(rename, |r| r.map(|(from, to)| Event::Rename { from, to, timestamp: ts })) |
+                    map!(execve, |e| Some(Event::Exec(Exec { timestamp: ts, ..e })))
+                ) >>
+        (event)
+    )
+);
+
+/// Returns true if `input` is a well-formed line about something that isn't
+/// reported: a signal, a call strace split in two, a call other than those
+/// of `Event`, or one of those that failed.
+fn is_skipped(input: &str) -> bool {
+    lazy_static! {
+        static ref NOTICE: Regex =
+            Regex::new(r"^(\d+\.\d+ )?(--- .* ---|\+\+\+ .* \+\+\+)$").unwrap();
+        static ref SPLIT: Regex =
+            Regex::new(r"^(\d+\.\d+ )?(<\.\.\. \w+ resumed>.*|.* <unfinished \.\.\.>)$").unwrap();
+        static ref SYSCALL: Regex = Regex::new(r"^(\d+\.\d+ )?(\w+)\(.*\) += (.*)$").unwrap();
+    }
+    const REPORTED: &[&str] = &[
+        "execve",
+        "clone",
+        "clone3",
+        "fork",
+        "vfork",
+        "open",
+        "openat",
+        "stat",
+        "lstat",
+        "newfstatat",
+        "statx",
+        "rename",
+        "renameat",
+        "renameat2",
+    ];
+    if NOTICE.is_match(input) || SPLIT.is_match(input) {
+        return true;
+    }
+    match SYSCALL.captures(input) {
+        Some(c) => {
+            let result = c.get(3).map_or("", |m| m.as_str());
+            !REPORTED.contains(&&c[2]) || result.starts_with("-1 ") || result == "?"
+        }
+        None => false,
     }
+}
 
-    Ok(None)
+/// Parses a line of an strace log. Lines about calls that aren't reported
+/// give None; malformed lines are an error.
+pub fn parseln(input: &str) -> Result<Option<Event>, String> {
+    match line(CompleteStr(input)) {
+        Ok((_, event)) => Ok(event),
+        Err(_) if is_skipped(input) => Ok(None),
+        Err(_) => Err(format!("failed to parse:\n {}", input)),
+    }
 }
 
 #[cfg(test)]
@@ -133,15 +430,43 @@ mod tests {
 
     const EMPTY: CompleteStr = CompleteStr("");
 
+    fn parsed(s: &str) -> Option<(&str, String)> {
+        string(CompleteStr(s)).ok().map(|(rest, s)| (rest.0, s))
+    }
+
     #[test]
     fn test_string() {
-        assert_eq!(string(CompleteStr("\"test\"")), Ok((EMPTY, "test")));
+        assert_eq!(parsed("\"test\""), Some(("", "test".to_owned())));
+        assert_eq!(parsed("\"te\"st\""), Some(("st\"", "te".to_owned())));
+        assert_eq!(parsed("\"\", x"), Some((", x", String::new())));
+        assert!(parsed("\"").is_none());
+        assert!(parsed("test").is_none());
+    }
+
+    #[test]
+    fn test_string_escapes() {
         assert_eq!(
-            string(CompleteStr("\"te\"st\"")),
-            Ok((CompleteStr("st\""), "te"))
+            parsed(r#""-DVERSION=\"1.0\"""#),
+            Some(("", "-DVERSION=\"1.0\"".to_owned()))
+        );
+        assert_eq!(
+            parsed(r#""a\\b\tc\nd""#),
+            Some(("", "a\\b\tc\nd".to_owned()))
+        );
+        assert_eq!(
+            parsed(r#""\303\251t\303\251\0""#),
+            Some(("", "\u{e9}t\u{e9}\0".to_owned()))
+        );
+        assert_eq!(parsed(r#""\x1b[0m""#), Some(("", "\x1b[0m".to_owned())));
+        assert!(parsed(r#""\"#).is_none());
+    }
+
+    #[test]
+    fn test_string_truncated() {
+        assert_eq!(
+            parsed("\"-DLONG=aaaa\"..., \"-c\""),
+            Some((", \"-c\"", "-DLONG=aaaa".to_owned()))
         );
-        assert!(string(CompleteStr("\"\"")).is_err());
-        assert!(string(CompleteStr("\"")).is_err());
     }
 
     #[test]
@@ -149,11 +474,14 @@ mod tests {
         assert_eq!(arr_of_str(CompleteStr("[]")), Ok((EMPTY, vec![])));
         assert_eq!(
             arr_of_str(CompleteStr("[\"test\"]")),
-            Ok((EMPTY, vec!["test"]))
+            Ok((EMPTY, vec!["test".to_owned()]))
         );
         assert_eq!(
-            arr_of_str(CompleteStr("[\"test\", \"best\"]")),
-            Ok((EMPTY, vec!["test", "best"]))
+            arr_of_str(CompleteStr("[\"test\", \"\", \"best\"]")),
+            Ok((
+                EMPTY,
+                vec!["test".to_owned(), String::new(), "best".to_owned()]
+            ))
         );
     }
 
@@ -164,14 +492,18 @@ mod tests {
 
     #[test]
     fn test_env_var() {
-        assert_eq!(
-            env_var(CompleteStr("\"key=value\"")),
-            Ok((EMPTY, ("key", "value")))
-        );
-        assert_eq!(
-            env_var(CompleteStr("\"key=value=value\"")),
-            Ok((EMPTY, ("key", "value=value")))
-        );
+        fn parsed(s: &str) -> Option<(String, String)> {
+            match env_var(CompleteStr(s)) {
+                Ok((EMPTY, var)) => Some(var),
+                _ => None,
+            }
+        }
+        let var = |k: &str, v: &str| Some((k.to_owned(), v.to_owned()));
+        assert_eq!(parsed("\"key=value\""), var("key", "value"));
+        assert_eq!(parsed("\"key=value=value\""), var("key", "value=value"));
+        assert_eq!(parsed("\"key=\""), var("key", ""));
+        assert_eq!(parsed(r#""PS1=\"\\u\" ""#), var("PS1", "\"\\u\" "));
+        assert_eq!(parsed("\"LONG=aa\"..."), var("LONG", "aa"));
     }
 
     #[test]
@@ -179,7 +511,7 @@ mod tests {
         assert_eq!(arr_of_env_var(CompleteStr("[]")), Ok((EMPTY, vec![])));
         assert_eq!(
             arr_of_env_var(CompleteStr("[\"key=value\"]")),
-            Ok((EMPTY, vec![("key", "value")]))
+            Ok((EMPTY, vec![("key".to_owned(), "value".to_owned())]))
         );
     }
 
@@ -190,13 +522,226 @@ mod tests {
             Ok((
                 EMPTY,
                 Exec {
+                    pid: 0,
                     path: "/bin/ls".to_string(),
                     args: vec!["-la".to_string()],
                     env: vec![],
-                    retcode: 0
+                    retcode: 0,
+                    timestamp: None
                 }
             ))
         );
+        let exec = parseln(
+            r#"execve("/usr/bin/gcc", ["gcc", "-DPACKAGE_NAME=\"foo\"", "", "-c", "a.c"], ["CFLAGS="]) = 0"#,
+        );
+        assert_eq!(
+            exec,
+            Ok(Some(Event::Exec(Exec {
+                pid: 0,
+                path: "/usr/bin/gcc".to_string(),
+                args: ["gcc", "-DPACKAGE_NAME=\"foo\"", "", "-c", "a.c"]
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect(),
+                env: vec![("CFLAGS".to_string(), String::new())],
+                retcode: 0,
+                timestamp: None
+            })))
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(
+            timestamp(CompleteStr("1553000000.123456 ")),
+            Ok((EMPTY, 1553000000.123456))
+        );
+        assert!(timestamp(CompleteStr("execve(")).is_err());
+        assert_eq!(
+            parseln("1553000000.5 execve(\"/bin/ls\", [\"ls\"], []) = 0"),
+            Ok(Some(Event::Exec(Exec {
+                pid: 0,
+                path: "/bin/ls".to_string(),
+                args: vec!["ls".to_string()],
+                env: vec![],
+                retcode: 0,
+                timestamp: Some(1553000000.5)
+            })))
+        );
+        assert_eq!(
+            parseln("1553000001.0 +++ exited with 0 +++"),
+            Ok(Some(Event::Exit {
+                retcode: Some(0),
+                timestamp: Some(1553000001.0)
+            }))
+        );
+    }
+
+    #[test]
+    fn test_fork() {
+        assert_eq!(
+            parseln("clone(child_stack=NULL, flags=CLONE_CHILD_CLEARTID|CLONE_CHILD_SETTID|SIGCHLD, child_tidptr=0x7f2c) = 1234"),
+            Ok(Some(Event::Fork {
+                child: 1234,
+                timestamp: None
+            }))
+        );
+        assert_eq!(
+            parseln("1.5 clone3({flags=CLONE_VM|CLONE_VFORK, exit_signal=SIGCHLD, stack=0x7f, stack_size=0x9000}, 88) = 42"),
+            Ok(Some(Event::Fork {
+                child: 42,
+                timestamp: Some(1.5)
+            }))
+        );
+        assert!(parseln("vfork() = 7").unwrap().is_some());
+        assert_eq!(
+            parseln("1.5 vfork()                                 = 1234"),
+            Ok(Some(Event::Fork {
+                child: 1234,
+                timestamp: Some(1.5)
+            }))
+        );
+        assert!(parseln("fork()   = 8").unwrap().is_some());
+        assert_eq!(
+            parseln(
+                "clone(child_stack=0x7f, flags=CLONE_VM|CLONE_FS|CLONE_THREAD|CLONE_SYSVSEM) = 99"
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            parseln("fork() = -1 EAGAIN (Resource temporarily unavailable)"),
+            Ok(None)
+        );
+        assert_eq!(
+            parseln("+++ killed by SIGSEGV (core dumped) +++"),
+            Ok(Some(Event::Exit {
+                retcode: None,
+                timestamp: None
+            }))
+        );
+    }
+
+    #[test]
+    fn test_open() {
+        assert_eq!(
+            parseln("1.5 openat(AT_FDCWD, \"/usr/include/stdio.h\", O_RDONLY|O_NOCTTY) = 3"),
+            Ok(Some(Event::Open {
+                path: "/usr/include/stdio.h".to_string(),
+                write: false,
+                timestamp: Some(1.5)
+            }))
+        );
+        assert_eq!(
+            parseln("open(\"foo.o\", O_WRONLY|O_CREAT|O_TRUNC, 0666) = 4"),
+            Ok(Some(Event::Open {
+                path: "foo.o".to_string(),
+                write: true,
+                timestamp: None
+            }))
+        );
+        assert_eq!(
+            parseln("open(\"foo.h\", O_RDONLY)                = 3"),
+            Ok(Some(Event::Open {
+                path: "foo.h".to_string(),
+                write: false,
+                timestamp: None
+            }))
+        );
+        assert_eq!(parseln("openat(5, \"x.h\", O_RDONLY) = 6"), Ok(None));
+        assert_eq!(
+            parseln("openat(AT_FDCWD, \"inc\", O_RDONLY|O_NONBLOCK|O_CLOEXEC|O_DIRECTORY) = 3"),
+            Ok(None)
+        );
+        assert_eq!(
+            parseln("openat(AT_FDCWD, \"x.h\", O_RDONLY) = -1 ENOENT (No such file or directory)"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_stat() {
+        let looked_up = |path: &str| {
+            Ok(Some(Event::Open {
+                path: path.to_string(),
+                write: false,
+                timestamp: None,
+            }))
+        };
+        assert_eq!(
+            parseln("stat(\"/usr/include/stdio.h\", {st_dev=makedev(0x8, 0x1), st_mode=S_IFREG|0644, st_size=16}) = 0"),
+            looked_up("/usr/include/stdio.h")
+        );
+        assert_eq!(
+            parseln("newfstatat(AT_FDCWD, \"inc/a.h\", {st_mode=S_IFREG|0644, st_size=10}, 0) = 0"),
+            looked_up("inc/a.h")
+        );
+        assert_eq!(
+            parseln("statx(AT_FDCWD, \"a.h\", AT_STATX_SYNC_AS_STAT, STATX_ALL, {stx_mask=STATX_BASIC_STATS, stx_mode=S_IFREG|0644, stx_atime={tv_sec=1, tv_nsec=0}}) = 0"),
+            looked_up("a.h")
+        );
+        assert_eq!(
+            parseln("newfstatat(AT_FDCWD, \"inc\", {st_mode=S_IFDIR|0755, st_size=4096}, 0) = 0"),
+            Ok(None)
+        );
+        assert_eq!(
+            parseln("newfstatat(3, \"\", {st_mode=S_IFREG|0644, st_size=10}, AT_EMPTY_PATH) = 0"),
+            Ok(None)
+        );
+        assert_eq!(
+            parseln("stat(\"b.h\", 0x7ffd) = -1 ENOENT (No such file or directory)"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let renamed = |from: &str, to: &str| {
+            Ok(Some(Event::Rename {
+                from: from.to_string(),
+                to: to.to_string(),
+                timestamp: None,
+            }))
+        };
+        assert_eq!(
+            parseln("rename(\"parse.tmp\", \"parse.c\") = 0"),
+            renamed("parse.tmp", "parse.c")
+        );
+        assert_eq!(
+            parseln("renameat(AT_FDCWD, \"a.tmp\", AT_FDCWD, \"a.h\") = 0"),
+            renamed("a.tmp", "a.h")
+        );
+        assert_eq!(
+            parseln("renameat2(AT_FDCWD, \"a\", 4, \"/b\", RENAME_NOREPLACE) = 0"),
+            renamed("a", "/b")
+        );
+        assert_eq!(
+            parseln("rename(\"a.tmp\", \"a.h\")                   = 0"),
+            renamed("a.tmp", "a.h")
+        );
+        assert_eq!(parseln("renameat(AT_FDCWD, \"a\", 4, \"b\") = 0"), Ok(None));
+        assert_eq!(
+            parseln("rename(\"a\", \"b\") = -1 ENOENT (No such file or directory)"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_skipped() {
+        let skipped = [
+            "1.5 wait4(-1, [{WIFEXITED(s) && WEXITSTATUS(s) == 0}], 0, NULL) = 1234",
+            "exit_group(0)                           = ?",
+            "--- SIGCHLD {si_signo=SIGCHLD, si_code=CLD_EXITED, si_pid=1234} ---",
+            "1.5 wait4(-1,  <unfinished ...>",
+            "1.6 <... wait4 resumed>[{WIFEXITED(s) && WEXITSTATUS(s) == 0}], 0, NULL) = 1234",
+            "execve(\"/usr/local/bin/cc\", [\"cc\"], []) = -1 ENOENT (No such file or directory)",
+            "+++ superseded by execve in pid 1234 +++",
+        ];
+        for line in &skipped {
+            assert_eq!(parseln(line), Ok(None), "{}", line);
+        }
+        assert!(parseln("execve(\"/bin/ls\", [\"ls\"").is_err());
+        assert!(parseln("openat(AT_FDCWD, \"x.h\", O_RDONLY").is_err());
+        assert!(parseln("garbage").is_err());
     }
 
     #[test]
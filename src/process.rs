@@ -0,0 +1,309 @@
+//! The tree of processes started by a traced build.
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::parser::Event;
+use crate::Exec;
+
+/// A file opened by a process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opened {
+    /// The path as passed to `open`, relative to the working directory of
+    /// the process unless absolute.
+    pub path: String,
+    pub write: bool,
+    /// The index in `Process::execs` of the program that opened the file;
+    /// None if the process hadn't executed any program yet.
+    pub exec: Option<usize>,
+    pub timestamp: Option<f64>,
+}
+
+/// A file renamed by a process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renamed {
+    /// The paths as passed to `rename`, relative to the working directory
+    /// of the process unless absolute.
+    pub from: String,
+    pub to: String,
+    /// As for `Opened::exec`.
+    pub exec: Option<usize>,
+    pub timestamp: Option<f64>,
+}
+
+/// A process of the build, which may execute several programs in turn.
+#[derive(Debug, Default, PartialEq)]
+pub struct Process {
+    pub pid: u32,
+    pub parent: Option<u32>,
+    pub children: Vec<u32>,
+    /// When the process was forked, or else its first recorded event.
+    pub start: Option<f64>,
+    /// When the process exited.
+    pub end: Option<f64>,
+    /// None if the process was killed or its exit wasn't recorded.
+    pub retcode: Option<u8>,
+    /// The programs executed by the process, in order.
+    pub execs: Vec<Exec>,
+    /// The files opened by the process, in order.
+    pub opened: Vec<Opened>,
+    /// The files renamed by the process, in order.
+    pub renamed: Vec<Renamed>,
+}
+
+impl Process {
+    /// The programs executed by the process, each with the time it ran
+    /// until: the next exec or the exit of the process.
+    pub fn spans(&self) -> impl Iterator<Item = (&Exec, Option<f64>)> {
+        self.execs.iter().enumerate().map(move |(i, e)| {
+            let end = self
+                .execs
+                .get(i + 1)
+                .map_or(self.end, |next| next.timestamp);
+            (e, end)
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessTree {
+    processes: BTreeMap<u32, Process>,
+    pub(crate) unparsed: Vec<(u32, String)>,
+}
+
+impl ProcessTree {
+    /// Builds the tree from the events recorded for each pid. Processes
+    /// without a parent that neither exec nor fork, such as the threads
+    /// of a multithreaded linker, are dropped.
+    pub fn from(v: Vec<(u32, Vec<Event>)>) -> Self {
+        let mut forks = HashMap::new();
+        let mut processes = BTreeMap::new();
+        for (pid, events) in v {
+            let mut p = Process {
+                pid,
+                ..Default::default()
+            };
+            for event in events {
+                match event {
+                    Event::Exec(e) => {
+                        p.start = p.start.or(e.timestamp);
+                        p.execs.push(Exec { pid, ..e });
+                    }
+                    Event::Open {
+                        path,
+                        write,
+                        timestamp,
+                    } => {
+                        p.start = p.start.or(timestamp);
+                        p.opened.push(Opened {
+                            path,
+                            write,
+                            exec: p.execs.len().checked_sub(1),
+                            timestamp,
+                        });
+                    }
+                    Event::Fork { child, timestamp } => {
+                        p.start = p.start.or(timestamp);
+                        p.children.push(child);
+                        forks.insert(child, (pid, timestamp));
+                    }
+                    Event::Rename {
+                        from,
+                        to,
+                        timestamp,
+                    } => {
+                        p.start = p.start.or(timestamp);
+                        p.renamed.push(Renamed {
+                            from,
+                            to,
+                            exec: p.execs.len().checked_sub(1),
+                            timestamp,
+                        });
+                    }
+                    Event::Exit { retcode, timestamp } => {
+                        p.start = p.start.or(timestamp);
+                        p.end = timestamp;
+                        p.retcode = retcode;
+                    }
+                }
+            }
+            processes.insert(pid, p);
+        }
+        for (child, (parent, timestamp)) in forks {
+            if let Some(p) = processes.get_mut(&child) {
+                p.parent = Some(parent);
+                p.start = timestamp.or(p.start);
+            }
+        }
+        processes
+            .retain(|_, p| p.parent.is_some() || !p.execs.is_empty() || !p.children.is_empty());
+        for p in processes.values_mut() {
+            p.children.sort();
+        }
+        ProcessTree {
+            processes,
+            unparsed: vec![],
+        }
+    }
+
+    /// The `execve` lines of each process that couldn't be parsed; the
+    /// programs they ran are missing from the tree.
+    pub fn unparsed(&self) -> &[(u32, String)] {
+        &self.unparsed
+    }
+
+    pub fn get(&self, pid: u32) -> Option<&Process> {
+        self.processes.get(&pid)
+    }
+
+    /// All processes, ordered by pid.
+    pub fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.processes.values()
+    }
+
+    /// The processes whose parent wasn't traced, usually just the build
+    /// command itself.
+    pub fn roots(&self) -> Vec<u32> {
+        self.processes()
+            .filter(|p| p.parent.is_none())
+            .map(|p| p.pid)
+            .collect()
+    }
+
+    /// The earliest time recorded for any process.
+    pub fn start(&self) -> Option<f64> {
+        self.processes()
+            .filter_map(|p| p.start)
+            .fold(None, |min, t| Some(min.map_or(t, |m: f64| m.min(t))))
+    }
+
+    /// The latest time recorded for any process.
+    pub fn end(&self) -> Option<f64> {
+        self.processes()
+            .filter_map(|p| p.end.or(p.start))
+            .fold(None, |max, t| Some(max.map_or(t, |m: f64| m.max(t))))
+    }
+
+    /// The process and its descendants, parents first.
+    pub fn subtree(&self, pid: u32) -> Vec<&Process> {
+        let mut res = vec![];
+        let mut stack = vec![pid];
+        while let Some(pid) = stack.pop() {
+            if let Some(p) = self.get(pid) {
+                res.push(p);
+                stack.extend(p.children.iter().rev());
+            }
+        }
+        res
+    }
+
+    /// All programs executed during the build.
+    pub fn execs(&self) -> impl Iterator<Item = &Exec> {
+        self.processes().flat_map(|p| p.execs.iter())
+    }
+
+    /// The program that `pid` was running when it performed a file access
+    /// recorded with `exec`. Accesses before the first exec, such as shell
+    /// redirections, are attributed to the program executed next, or else
+    /// to the program of the parent.
+    pub fn program(&self, pid: u32, exec: Option<usize>) -> Option<&Exec> {
+        let p = self.get(pid)?;
+        match exec {
+            Some(i) => p.execs.get(i),
+            None => p.execs.first().or_else(|| {
+                let mut next = p.parent.and_then(|parent| self.get(parent));
+                while let Some(p) = next {
+                    if let Some(e) = p.execs.last() {
+                        return Some(e);
+                    }
+                    next = p.parent.and_then(|parent| self.get(parent));
+                }
+                None
+            }),
+        }
+    }
+
+    /// The name of the program `pid` was running last: the file name of its
+    /// last exec, or that of its parent if it didn't exec.
+    pub fn name(&self, pid: u32) -> &str {
+        let mut next = self.get(pid);
+        while let Some(p) = next {
+            if let Some(e) = p.execs.last() {
+                return Path::new(&e.path)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or(&e.path);
+            }
+            next = p.parent.and_then(|parent| self.get(parent));
+        }
+        "?"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec(path: &str, timestamp: f64) -> Event {
+        let mut e = Exec::mock(path, &[]);
+        e.timestamp = Some(timestamp);
+        Event::Exec(e)
+    }
+
+    fn fork(child: u32, timestamp: f64) -> Event {
+        Event::Fork {
+            child,
+            timestamp: Some(timestamp),
+        }
+    }
+
+    fn exit(retcode: u8, timestamp: f64) -> Event {
+        Event::Exit {
+            retcode: Some(retcode),
+            timestamp: Some(timestamp),
+        }
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let tree = ProcessTree::from(vec![
+            (
+                12,
+                vec![exec("/usr/bin/cc", 2.0), fork(13, 2.5), exit(0, 4.0)],
+            ),
+            (
+                10,
+                vec![exec("/usr/bin/make", 1.0), fork(11, 1.5), exit(0, 5.0)],
+            ),
+            (13, vec![exec("/usr/lib/gcc/cc1", 2.6), exit(0, 3.5)]),
+            (11, vec![fork(12, 1.8), exit(0, 4.5)]),
+            // a thread of make
+            (14, vec![exit(0, 2.0)]),
+        ]);
+        assert_eq!(tree.roots(), vec![10]);
+        assert_eq!(tree.start(), Some(1.0));
+        assert_eq!(tree.end(), Some(5.0));
+        assert_eq!(tree.get(10).unwrap().children, vec![11]);
+        assert_eq!(tree.get(12).unwrap().parent, Some(11));
+        assert_eq!(tree.get(12).unwrap().start, Some(1.8));
+        assert_eq!(tree.get(12).unwrap().end, Some(4.0));
+        assert_eq!(tree.name(11), "make");
+        assert_eq!(tree.name(13), "cc1");
+        assert!(tree.get(14).is_none());
+        assert_eq!(tree.execs().count(), 3);
+        assert!(tree.execs().all(|e| e.pid != 0));
+        let pids = tree.subtree(11).iter().map(|p| p.pid).collect::<Vec<_>>();
+        assert_eq!(pids, vec![11, 12, 13]);
+
+        let p = ProcessTree::from(vec![(
+            1,
+            vec![exec("/bin/sh", 1.0), exec("/usr/bin/cc", 2.0), exit(0, 3.0)],
+        )]);
+        let ends = p
+            .get(1)
+            .unwrap()
+            .spans()
+            .map(|(_, end)| end)
+            .collect::<Vec<_>>();
+        assert_eq!(ends, vec![Some(2.0), Some(3.0)]);
+    }
+}
@@ -0,0 +1,267 @@
+//! Callbacks receiving the events of a traced build one at a time, while
+//! it runs.
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use crate::parser::Event;
+use crate::Exec;
+
+/// Receives the events of a traced build as strace writes them, in the
+/// order they happened. Returning `ControlFlow::Break(())` from any callback
+/// stops the events delivered to the sink, and the build once every sink
+/// has stopped; the defaults ignore the event and continue. Closures taking
+/// an `Exec` are sinks receiving only execs.
+pub trait EventSink {
+    /// A process ran a program.
+    fn on_exec(&mut self, _exec: &Exec) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The process `parent` created the process `child`.
+    fn on_fork(&mut self, _parent: u32, _child: u32, _timestamp: Option<f64>) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The process `pid` opened `path`, for writing if `write` is set.
+    fn on_open(
+        &mut self,
+        _pid: u32,
+        _path: &str,
+        _write: bool,
+        _timestamp: Option<f64>,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The process `pid` renamed `from` to `to`.
+    fn on_rename(
+        &mut self,
+        _pid: u32,
+        _from: &str,
+        _to: &str,
+        _timestamp: Option<f64>,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// The process `pid` exited with `retcode`, or was killed by a signal
+    /// if that is None.
+    fn on_exit(
+        &mut self,
+        _pid: u32,
+        _retcode: Option<u8>,
+        _timestamp: Option<f64>,
+    ) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// A line of the strace log of `pid` is malformed. Well-formed lines
+    /// about anything but the events above, like the `wait4` calls strace
+    /// also reports, are skipped.
+    fn on_parse_error(&mut self, _pid: u32, _line: &str) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl<F: FnMut(&Exec) -> ControlFlow<()>> EventSink for F {
+    fn on_exec(&mut self, exec: &Exec) -> ControlFlow<()> {
+        self(exec)
+    }
+}
+
+/// A line of the strace log of a process: an event, or the line if it
+/// couldn't be parsed.
+pub type LogEntry = Result<Event, String>;
+
+fn deliver(sink: &mut dyn EventSink, pid: u32, entry: &LogEntry) -> ControlFlow<()> {
+    match entry {
+        Ok(Event::Exec(e)) => sink.on_exec(e),
+        Ok(Event::Fork { child, timestamp }) => sink.on_fork(pid, *child, *timestamp),
+        Ok(Event::Open {
+            path,
+            write,
+            timestamp,
+        }) => sink.on_open(pid, path, *write, *timestamp),
+        Ok(Event::Rename {
+            from,
+            to,
+            timestamp,
+        }) => sink.on_rename(pid, from, to, *timestamp),
+        Ok(Event::Exit { retcode, timestamp }) => sink.on_exit(pid, *retcode, *timestamp),
+        Err(line) => sink.on_parse_error(pid, line),
+    }
+}
+
+/// Delivers log entries to sinks, remembering which of them have stopped.
+pub(crate) struct Dispatcher<'a, 'b> {
+    sinks: &'a mut [&'b mut dyn EventSink],
+    active: Vec<bool>,
+    /// The time of the last event of each process, which its unparsed
+    /// lines are ordered by.
+    times: HashMap<u32, f64>,
+}
+
+impl<'a, 'b> Dispatcher<'a, 'b> {
+    pub fn new(sinks: &'a mut [&'b mut dyn EventSink]) -> Self {
+        let active = vec![true; sinks.len()];
+        Dispatcher {
+            sinks,
+            active,
+            times: HashMap::new(),
+        }
+    }
+
+    /// Returns true once every sink has stopped.
+    pub fn stopped(&self) -> bool {
+        !self.active.contains(&true)
+    }
+
+    /// Delivers the entries of the logs of each process, ordered by time
+    /// across processes. Unparsed lines keep their place in the log of
+    /// their process.
+    pub fn dispatch(&mut self, logs: Vec<(u32, Vec<LogEntry>)>) {
+        let mut entries = vec![];
+        for (pid, log) in logs {
+            let time = self.times.entry(pid).or_insert(f64::NEG_INFINITY);
+            for mut entry in log {
+                if let Ok(event) = &mut entry {
+                    if let Event::Exec(e) = event {
+                        e.pid = pid;
+                    }
+                    *time = event.timestamp().unwrap_or(*time);
+                }
+                entries.push((*time, pid, entry));
+            }
+        }
+        // stable, so the entries of a process stay in order
+        entries.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        for (_, pid, entry) in entries {
+            if self.stopped() {
+                break;
+            }
+            for (sink, active) in self.sinks.iter_mut().zip(self.active.iter_mut()) {
+                if *active && deliver(&mut **sink, pid, &entry).is_break() {
+                    *active = false;
+                }
+            }
+        }
+    }
+}
+
+/// Delivers the entries of the logs of each process to `sinks`, ordered by
+/// time across processes, until every sink has stopped. Unparsed lines
+/// keep their place in the log of their process.
+pub fn dispatch(logs: Vec<(u32, Vec<LogEntry>)>, sinks: &mut [&mut dyn EventSink]) {
+    Dispatcher::new(sinks).dispatch(logs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorder {
+        seen: Vec<String>,
+        limit: usize,
+    }
+
+    impl Recorder {
+        fn record(&mut self, s: String) -> ControlFlow<()> {
+            self.seen.push(s);
+            if self.seen.len() == self.limit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    impl EventSink for Recorder {
+        fn on_exec(&mut self, exec: &Exec) -> ControlFlow<()> {
+            self.record(format!("{} exec {}", exec.pid, exec.path))
+        }
+
+        fn on_fork(&mut self, parent: u32, child: u32, _timestamp: Option<f64>) -> ControlFlow<()> {
+            self.record(format!("{} fork {}", parent, child))
+        }
+
+        fn on_exit(
+            &mut self,
+            pid: u32,
+            _retcode: Option<u8>,
+            _timestamp: Option<f64>,
+        ) -> ControlFlow<()> {
+            self.record(format!("{} exit", pid))
+        }
+
+        fn on_parse_error(&mut self, pid: u32, line: &str) -> ControlFlow<()> {
+            self.record(format!("{} ? {}", pid, line))
+        }
+    }
+
+    fn exec(path: &str, timestamp: f64) -> LogEntry {
+        let mut e = Exec::mock(path, &[]);
+        e.timestamp = Some(timestamp);
+        Ok(Event::Exec(e))
+    }
+
+    fn exit(timestamp: f64) -> LogEntry {
+        Ok(Event::Exit {
+            retcode: Some(0),
+            timestamp: Some(timestamp),
+        })
+    }
+
+    fn logs() -> Vec<(u32, Vec<LogEntry>)> {
+        vec![
+            (
+                2,
+                vec![
+                    exec("/usr/bin/cc", 1.0),
+                    Err("execve(\"/usr/bin/cc".to_owned()),
+                    exit(3.0),
+                ],
+            ),
+            (
+                1,
+                vec![
+                    exec("/usr/bin/make", 0.0),
+                    Ok(Event::Fork {
+                        child: 2,
+                        timestamp: Some(0.5),
+                    }),
+                    exit(4.0),
+                ],
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let mut all = Recorder::default();
+        let mut first = Recorder {
+            limit: 2,
+            ..Default::default()
+        };
+        let mut execs = vec![];
+        let mut on_exec = |e: &Exec| {
+            execs.push(e.path.clone());
+            ControlFlow::Continue(())
+        };
+        dispatch(logs(), &mut [&mut all, &mut first, &mut on_exec]);
+        assert_eq!(
+            all.seen,
+            vec![
+                "1 exec /usr/bin/make",
+                "1 fork 2",
+                "2 exec /usr/bin/cc",
+                "2 ? execve(\"/usr/bin/cc",
+                "2 exit",
+                "1 exit",
+            ]
+        );
+        assert_eq!(first.seen, vec!["1 exec /usr/bin/make", "1 fork 2"]);
+        assert_eq!(execs, vec!["/usr/bin/make", "/usr/bin/cc"]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::error::{write_json, Result};
+use crate::tools::paths;
+use crate::tools::ToolKind;
+use crate::Exec;
+
+/// The operation performed by an `ar` invocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArOperation {
+    /// `r`: insert members, replacing existing ones with the same name.
+    Replace,
+    /// `q`: append members without checking for existing ones.
+    QuickAppend,
+    /// `d`: delete members.
+    Delete,
+    /// `m`: move members within the archive.
+    Move,
+    /// `s` on its own or `ranlib`: (re)build the symbol index.
+    Index,
+    /// `p`, `t` or `x`: read the archive without modifying it.
+    Read,
+}
+
+/// A single `ar` or `ranlib` invocation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveCmd {
+    /// The working directory of the invocation.
+    pub directory: String,
+    /// The archive operated on, relative to `directory` unless absolute.
+    pub archive: String,
+    pub operation: ArOperation,
+    /// `T`: a thin archive which references its members by path.
+    pub thin: bool,
+    /// The member files named on the command line.
+    pub members: Vec<String>,
+    pub timestamp: Option<f64>,
+}
+
+impl ArchiveCmd {
+    /// Parses `ar [-]p[mod] [relpos] [count] archive [member...]`, as well
+    /// as `ranlib archive...`. Returns one entry per archive since `ranlib`
+    /// accepts several.
+    pub fn from(e: &Exec) -> Vec<Self> {
+        let directory = match e.env.iter().find(|(k, _v)| k == "PWD") {
+            Some((_k, v)) => v.clone(),
+            None => return vec![],
+        };
+        let tool = Path::new(&e.path)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or("");
+        let mk = |archive: &str, operation, thin, members: &[String]| ArchiveCmd {
+            directory: directory.clone(),
+            archive: archive.to_owned(),
+            operation,
+            thin,
+            members: members.to_vec(),
+            timestamp: e.timestamp,
+        };
+
+        // drop long options, some of which take an operand
+        let mut args = vec![];
+        let mut iter = e.args.iter().skip(1);
+        while let Some(a) = iter.next() {
+            match a.as_str() {
+                "--plugin" | "--target" | "--output" => {
+                    iter.next();
+                }
+                "--thin" => args.push("T".to_owned()),
+                a if a.starts_with("--") || a.starts_with("-X") => {}
+                _ => args.push(a.clone()),
+            }
+        }
+
+        if tool.contains("ranlib") {
+            return args
+                .iter()
+                .filter(|a| !a.starts_with('-'))
+                .map(|a| mk(a, ArOperation::Index, false, &[]))
+                .collect();
+        }
+
+        let mut args = args.into_iter();
+        let key = match args.next() {
+            Some(key) => key.trim_start_matches('-').to_owned(),
+            None => return vec![],
+        };
+        let mut operation = None;
+        let mut thin = false;
+        let mut positional = 0;
+        for c in key.chars() {
+            match c {
+                'r' => operation = Some(ArOperation::Replace),
+                'q' => operation = Some(ArOperation::QuickAppend),
+                'd' => operation = Some(ArOperation::Delete),
+                'm' => operation = Some(ArOperation::Move),
+                'p' | 't' | 'x' => operation = Some(ArOperation::Read),
+                's' if operation.is_none() => operation = Some(ArOperation::Index),
+                'T' => thin = true,
+                // a relative position member name precedes the archive
+                'a' | 'b' | 'i' => positional += 1,
+                // so does the instance count
+                'N' => positional += 1,
+                _ => {}
+            }
+        }
+        let operation = match operation {
+            Some(op) => op,
+            None => return vec![],
+        };
+        let rest = args.skip(positional).collect::<Vec<String>>();
+        match rest.split_first() {
+            Some((archive, members)) => vec![mk(archive, operation, thin, members)],
+            None => vec![],
+        }
+    }
+}
+
+/// The members of an archive after all observed modifications.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Archive {
+    /// The absolute path of the archive.
+    pub archive: String,
+    pub thin: bool,
+    /// The absolute paths of the files added to the archive, in order.
+    pub members: Vec<String>,
+}
+
+/// Tracks archive membership by replaying `ar` invocations.
+#[derive(Debug, Default)]
+pub struct ArchiveIndex {
+    archives: BTreeMap<String, Archive>,
+}
+
+fn absolute(dir: &str, path: &str) -> String {
+    paths::clean(&Path::new(dir).join(path))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn same_member(a: &str, b: &str) -> bool {
+    Path::new(a).file_name() == Path::new(b).file_name()
+}
+
+/// Returns true if `e` runs `gcc-ar` or `gcc-ranlib`, which run `ar` itself
+/// with the same operation. `llvm-ar` is an archiver in its own right.
+fn is_wrapper(e: &Exec) -> bool {
+    lazy_static! {
+        static ref WRAPPER: Regex = Regex::new(r"^([^-]*-)*gcc-(ar|ranlib)(-[0-9.]+)?$").unwrap();
+    }
+    Path::new(&e.path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .is_some_and(|f| WRAPPER.is_match(f))
+}
+
+/// Returns true if `a` and `b` perform the same operation on the same
+/// archive and members.
+fn same_archive_cmd(a: &ArchiveCmd, b: &ArchiveCmd) -> bool {
+    (&a.directory, &a.archive, a.operation, a.thin, &a.members)
+        == (&b.directory, &b.archive, b.operation, b.thin, &b.members)
+}
+
+impl ArchiveIndex {
+    /// Builds the index from `ar` invocations among `v`, applied in
+    /// execution order if timestamps are available. `gcc-ar` and
+    /// `gcc-ranlib` run `ar` themselves, so an `ar` invocation repeating
+    /// that of one of them is applied only once.
+    pub fn from(v: &[(Exec, ToolKind)]) -> Self {
+        let (wrapped, direct): (Vec<_>, Vec<_>) = v
+            .iter()
+            .filter(|(_, t)| t == &ToolKind::Archiver)
+            .flat_map(|(e, _)| {
+                ArchiveCmd::from(e)
+                    .into_iter()
+                    .map(move |c| (c, is_wrapper(e)))
+            })
+            .partition(|(_, is_wrapper)| *is_wrapper);
+        let mut cmds = wrapped.into_iter().map(|(c, _)| c).collect::<Vec<_>>();
+        let mut unmatched = cmds.clone();
+        for (c, _) in direct {
+            match unmatched.iter().position(|w| same_archive_cmd(w, &c)) {
+                Some(i) => {
+                    unmatched.remove(i);
+                }
+                None => cmds.push(c),
+            }
+        }
+        cmds.sort_by(|a, b| {
+            let (a, b) = (a.timestamp.unwrap_or(0.0), b.timestamp.unwrap_or(0.0));
+            a.partial_cmp(&b).unwrap()
+        });
+        let mut index = ArchiveIndex::default();
+        for cmd in &cmds {
+            index.apply(cmd);
+        }
+        index
+    }
+
+    /// Updates the index with the effect of `cmd`.
+    pub fn apply(&mut self, cmd: &ArchiveCmd) {
+        let key = absolute(&cmd.directory, &cmd.archive);
+        let members = cmd
+            .members
+            .iter()
+            .map(|m| absolute(&cmd.directory, m))
+            .collect::<Vec<_>>();
+        match cmd.operation {
+            ArOperation::Replace | ArOperation::QuickAppend => {
+                let archive = self.archives.entry(key.clone()).or_insert(Archive {
+                    archive: key,
+                    thin: cmd.thin,
+                    members: vec![],
+                });
+                archive.thin |= cmd.thin;
+                for m in members {
+                    let existing = archive.members.iter().position(|e| same_member(e, &m));
+                    match existing {
+                        Some(i) if cmd.operation == ArOperation::Replace => archive.members[i] = m,
+                        _ => archive.members.push(m),
+                    }
+                }
+            }
+            ArOperation::Delete => {
+                if let Some(archive) = self.archives.get_mut(&key) {
+                    archive
+                        .members
+                        .retain(|e| !members.iter().any(|m| same_member(e, m)));
+                }
+            }
+            ArOperation::Move | ArOperation::Index | ArOperation::Read => {}
+        }
+    }
+
+    pub fn archives(&self) -> impl Iterator<Item = &Archive> {
+        self.archives.values()
+    }
+}
+
+pub fn write_archives(v: &[(Exec, ToolKind)]) -> Result<()> {
+    let index = ArchiveIndex::from(v);
+    let archives = index.archives().collect::<Vec<_>>();
+    write_json("archives.json", &archives)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ar(path: &str, args: &[&str], timestamp: f64) -> Exec {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        e.timestamp = Some(timestamp);
+        e
+    }
+
+    #[test]
+    fn test_archive_cmd_from() {
+        let cmds = ArchiveCmd::from(&ar("/usr/bin/ar", &["rcs", "libfoo.a", "a.o", "b.o"], 0.0));
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].operation, ArOperation::Replace);
+        assert_eq!(cmds[0].archive, "libfoo.a");
+        assert_eq!(cmds[0].members, vec!["a.o", "b.o"]);
+        assert!(!cmds[0].thin);
+
+        let cmds = ArchiveCmd::from(&ar(
+            "/usr/bin/gcc-ar",
+            &["--plugin", "liblto.so", "-qT", "libt.a", "c.o"],
+            0.0,
+        ));
+        assert_eq!(cmds[0].operation, ArOperation::QuickAppend);
+        assert_eq!(cmds[0].archive, "libt.a");
+        assert!(cmds[0].thin);
+
+        let cmds = ArchiveCmd::from(&ar("/usr/bin/ar", &["rb", "b.o", "lib.a", "x.o"], 0.0));
+        assert_eq!(cmds[0].archive, "lib.a");
+        assert_eq!(cmds[0].members, vec!["x.o"]);
+
+        let cmds = ArchiveCmd::from(&ar("/usr/bin/llvm-ranlib", &["a.a", "b.a"], 0.0));
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(cmds[1].operation, ArOperation::Index);
+
+        assert!(ArchiveCmd::from(&ar("/usr/bin/ar", &["--version"], 0.0)).is_empty());
+    }
+
+    #[test]
+    fn test_archive_index() {
+        let execs = vec![
+            ar("/usr/bin/ar", &["d", "lib.a", "a.o"], 3.0),
+            ar("/usr/bin/ar", &["rc", "lib.a", "a.o", "sub/b.o"], 1.0),
+            ar("/usr/bin/ar", &["r", "lib.a", "c.o", "b.o"], 2.0),
+            ar("/usr/bin/ranlib", &["lib.a"], 4.0),
+        ]
+        .into_iter()
+        .map(|e| (e, ToolKind::Archiver))
+        .collect::<Vec<_>>();
+        let index = ArchiveIndex::from(&execs);
+        assert_eq!(
+            index.archives().next().unwrap().members,
+            vec!["/build/b.o", "/build/c.o"]
+        );
+    }
+
+    #[test]
+    fn test_archive_wrapper() {
+        let execs = vec![
+            ar("/usr/bin/gcc-ar-12", &["qc", "lib.a", "a.o"], 1.0),
+            ar(
+                "/usr/bin/ar",
+                &["--plugin", "liblto_plugin.so", "qc", "lib.a", "a.o"],
+                1.1,
+            ),
+            ar("/usr/bin/ar", &["q", "lib.a", "b.o"], 2.0),
+        ]
+        .into_iter()
+        .map(|e| (e, ToolKind::Archiver))
+        .collect::<Vec<_>>();
+        let index = ArchiveIndex::from(&execs);
+        assert_eq!(
+            index.archives().next().unwrap().members,
+            vec!["/build/a.o", "/build/b.o"]
+        );
+
+        // llvm-ar doesn't run ar, so both quick appends happened
+        let execs = vec![
+            ar("/usr/bin/llvm-ar", &["qc", "lib.a", "a.o"], 1.0),
+            ar("/usr/bin/ar", &["qc", "lib.a", "a.o"], 2.0),
+        ]
+        .into_iter()
+        .map(|e| (e, ToolKind::Archiver))
+        .collect::<Vec<_>>();
+        let index = ArchiveIndex::from(&execs);
+        assert_eq!(
+            index.archives().next().unwrap().members,
+            vec!["/build/a.o", "/build/a.o"]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Built-in include paths, target and macros of the compilers used by a
+//! build, which tools based on a different compiler can't find by
+//! themselves.
+use std::collections::{BTreeMap, HashMap};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use crate::error::{write_json, Result};
+
+/// What to do with the built-ins of each compiler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuiltinsMode {
+    /// Add `-isystem` options for the built-in include directories and a
+    /// `--target` option to each entry.
+    Inject,
+    /// Write them to `compiler_builtins.json`.
+    Sidecar,
+}
+
+impl FromStr for BuiltinsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "inject" => Ok(BuiltinsMode::Inject),
+            "sidecar" => Ok(BuiltinsMode::Sidecar),
+            _ => Err(format!("unknown built-ins mode: {}", s)),
+        }
+    }
+}
+
+impl BuiltinsMode {
+    pub const NAMES: &'static [&'static str] = &["inject", "sidecar"];
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Builtins {
+    pub compiler: String,
+    /// `c` or `c++`.
+    pub language: String,
+    /// The target triple reported by `-v`.
+    pub target: Option<String>,
+    /// The directories searched for `#include <...>`, in order.
+    pub include_dirs: Vec<String>,
+    pub macros: BTreeMap<String, String>,
+}
+
+/// Extracts the target and the `#include <...>` search list from the
+/// output of `-E -v`.
+fn parse_verbose(output: &str) -> (Option<String>, Vec<String>) {
+    let target = output
+        .lines()
+        .find_map(|l| l.strip_prefix("Target: "))
+        .map(|t| t.trim().to_owned());
+    let dirs = output
+        .lines()
+        .skip_while(|l| !l.starts_with("#include <...> search starts here:"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("End of search list."))
+        // clang also lists macOS framework directories
+        .filter(|l| !l.ends_with("(framework directory)"))
+        .map(|l| l.trim().to_owned())
+        .collect();
+    (target, dirs)
+}
+
+/// Parses the `#define NAME VALUE` lines printed by `-dM -E`.
+fn parse_macros(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|l| l.strip_prefix("#define "))
+        .map(|l| match l.find(' ') {
+            Some(i) => (l[..i].to_owned(), l[i + 1..].to_owned()),
+            None => (l.to_owned(), String::new()),
+        })
+        .collect()
+}
+
+/// Runs `compiler` on an empty `language` input with `args`; returns its
+/// stdout and stderr if it succeeded.
+fn run(compiler: &str, language: &str, args: &[&str]) -> Option<(String, String)> {
+    let output = Command::new(compiler)
+        .args(args)
+        .args(["-x", language, "-"])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some((
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+/// Queries the built-ins of `compiler` for `language`, or None if it
+/// can't be run anymore.
+pub fn query(compiler: &str, language: &str) -> Option<Builtins> {
+    let (_, verbose) = run(compiler, language, &["-E", "-v"])?;
+    let (macros, _) = run(compiler, language, &["-dM", "-E"])?;
+    let (target, include_dirs) = parse_verbose(&verbose);
+    Some(Builtins {
+        compiler: compiler.to_owned(),
+        language: language.to_owned(),
+        target,
+        include_dirs,
+        macros: parse_macros(&macros),
+    })
+}
+
+/// Queries each compiler once per language.
+#[derive(Debug, Default)]
+pub struct BuiltinsCache {
+    cache: HashMap<(String, String), Option<Builtins>>,
+}
+
+impl BuiltinsCache {
+    pub fn get(&mut self, compiler: &str, language: &str) -> Option<&Builtins> {
+        self.cache
+            .entry((compiler.to_owned(), language.to_owned()))
+            .or_insert_with(|| query(compiler, language))
+            .as_ref()
+    }
+
+    /// The built-ins queried so far, sorted by compiler and language.
+    pub fn builtins(&self) -> Vec<&Builtins> {
+        let mut res = self.cache.values().flatten().collect::<Vec<_>>();
+        res.sort_by(|a, b| (&a.compiler, &a.language).cmp(&(&b.compiler, &b.language)));
+        res
+    }
+}
+
+pub fn write_builtins(cache: &BuiltinsCache) -> Result<()> {
+    write_json("compiler_builtins.json", &cache.builtins())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_verbose() {
+        let output = "Using built-in specs.
+COLLECT_GCC=arm-none-eabi-gcc
+Target: arm-none-eabi
+#include \"...\" search starts here:
+#include <...> search starts here:
+ /usr/lib/gcc/arm-none-eabi/12/include
+ /usr/lib/gcc/arm-none-eabi/12/include-fixed
+ /System/Library/Frameworks (framework directory)
+End of search list.
+";
+        let (target, dirs) = parse_verbose(output);
+        assert_eq!(target.as_deref(), Some("arm-none-eabi"));
+        assert_eq!(
+            dirs,
+            vec![
+                "/usr/lib/gcc/arm-none-eabi/12/include",
+                "/usr/lib/gcc/arm-none-eabi/12/include-fixed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_macros() {
+        let macros = parse_macros(
+            "#define __arm__ 1\n#define __VERSION__ \"12.2 rel1\"\n#define __STDC__\n",
+        );
+        assert_eq!(macros["__arm__"], "1");
+        assert_eq!(macros["__VERSION__"], "\"12.2 rel1\"");
+        assert_eq!(macros["__STDC__"], "");
+    }
+}
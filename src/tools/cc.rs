@@ -1,119 +1,232 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 use regex::Regex;
-use serde_json::Result;
 
+use crate::error::{write_json, Result};
+use crate::process::ProcessTree;
+use crate::tools::builtins::{write_builtins, Builtins, BuiltinsCache, BuiltinsMode};
+use crate::tools::ccmd::{CompileCmd, Generated};
+use crate::tools::driver::{self, Language, LanguageSwitch};
+use crate::tools::flags::FilterPolicy;
+use crate::tools::generated::{generated_files, generated_inputs};
+use crate::tools::includes::headers_read;
+use crate::tools::paths::{self, PathOptions};
+use crate::tools::target::{is_selected, target_key};
 use crate::tools::{CompilerAction, ToolKind};
 use crate::Exec;
 
-include!("ccmd.rs");
+/// Controls which commands end up in `compile_commands.json`.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Emit entries for assembly sources and direct assembler invocations.
+    /// Off by default to match the output of intercept-build.
+    pub assembly: bool,
+    /// Flags dropped from the recorded commands.
+    pub flags: FilterPolicy,
+    /// Emit the shell-escaped `command` form rather than `arguments`.
+    pub command: bool,
+    /// Record the `output` of each entry, which intercept-build leaves out.
+    pub output: bool,
+    /// Rewrites applied to the paths in each entry.
+    pub paths: PathOptions,
+    /// Keep every compilation of a translation unit rather than collapsing
+    /// identical entries, ordered by execution time if known.
+    pub keep_duplicates: bool,
+    /// Environment variables recorded with each entry; a trailing `*`
+    /// matches a prefix. Nothing is recorded if empty.
+    pub environment: Vec<String>,
+    /// Record the headers read by each compilation.
+    pub headers: bool,
+    /// Record which of the files read by each compilation were generated.
+    pub generated: bool,
+    /// Query the built-in include directories, target and macros of each
+    /// compiler and add them to the entries or write them to a file.
+    pub builtins: Option<BuiltinsMode>,
+    /// Record the target and configuration of each entry.
+    pub target: bool,
+    /// Only keep entries for these targets or configurations, if any.
+    pub targets: Vec<String>,
+    /// Only keep entries whose file or directory matches one of these
+    /// globs, if any.
+    pub include: Vec<Regex>,
+    /// Drop entries whose file or directory matches one of these globs.
+    pub exclude: Vec<Regex>,
+}
+
+/// The variables recorded by `--environment`: those changing the header
+/// search path or the behavior of the compiler and compiler caches.
+pub const DEFAULT_ENVIRONMENT: &[&str] = &[
+    "CPATH",
+    "C_INCLUDE_PATH",
+    "CPLUS_INCLUDE_PATH",
+    "OBJC_INCLUDE_PATH",
+    "SDKROOT",
+    "GCC_EXEC_PREFIX",
+    "COMPILER_PATH",
+    "CCACHE_*",
+];
+
+/// The variables of `env` matching one of the `allowed` names.
+fn filter_env(env: &[(String, String)], allowed: &[String]) -> BTreeMap<String, String> {
+    env.iter()
+        .filter(|(k, _v)| {
+            allowed.iter().any(|a| match a.strip_suffix('*') {
+                Some(prefix) => k.starts_with(prefix),
+                None => k == a,
+            })
+        })
+        .cloned()
+        .collect()
+}
 
 impl CompileCmd {
-    fn try_from(e: Exec, t: ToolKind) -> Option<Self> {
-        let path = &e.env.iter().find(|(k, _v)| k == "PWD").unwrap().1;
-        let (mut arguments, file) = filter_args(e.args);
+    fn try_from(e: Exec, t: ToolKind, opts: &Options) -> Option<Self> {
+        let path = pwd(&e)?.clone();
+        let environment = filter_env(&e.env, &opts.environment);
+        if t == ToolKind::Assembler {
+            // compiler drivers assemble their output through a temporary
+            // file; only keep assembler invocations on actual sources.
+            let tmp_dir = e
+                .env
+                .iter()
+                .find(|(k, _v)| k == "TMPDIR")
+                .map_or("/tmp", |(_k, v)| v.as_str());
+            return filter_as_args(e.args, opts)
+                .filter(|(_, file)| !Path::new(file).starts_with(tmp_dir))
+                .map(|(arguments, file)| CompileCmd {
+                    directory: path.to_string(),
+                    file,
+                    command: None,
+                    arguments,
+                    output: None,
+                    environment,
+                    headers: vec![],
+                    generated: vec![],
+                    target: None,
+                })
+                .filter(|cmd| cmd.is_included(opts))
+                .map(|cmd| cmd.finish(opts));
+        }
+
+        let (mut arguments, file) = filter_args(e.args, opts);
         if file.is_none() {
             return None;
         }
 
-        arguments[0] = match t {
-            ToolKind::CCompiler(_) => "cc".to_owned(),
-            ToolKind::CXXCompiler(_) => "c++".to_owned(),
-            _ => panic!(),
-        };
-        arguments.insert(1, "-c".to_owned());
+        if opts.flags.normalizes_driver() {
+            arguments[0] = match t {
+                ToolKind::CCompiler(_) => "cc".to_owned(),
+                ToolKind::CXXCompiler(_) => "c++".to_owned(),
+                _ => panic!(),
+            };
+            // `-c` is still there if it was kept
+            if !arguments.iter().any(|a| a == "-c") {
+                arguments.insert(1, "-c".to_owned());
+            }
+        }
 
-        Some(CompileCmd {
+        let cmd = CompileCmd {
             directory: path.to_string(),
             file: file.unwrap(),
             command: None,
             arguments,
-            output: None, // TODO: should use this field
-        })
+            output: None,
+            environment,
+            headers: vec![],
+            generated: vec![],
+            target: None,
+        };
+        if !cmd.is_included(opts) {
+            return None;
+        }
+        Some(cmd.finish(opts))
     }
-}
 
-fn is_source(file: &str) -> bool {
-    lazy_static! {
-        static ref SRC_EXT: HashSet<&'static str> = {
-            let mut s = HashSet::new();
-            s.insert("c");
-            s.insert("i");
-            s.insert("ii");
-            s.insert("m");
-            s.insert("mm");
-            s.insert("mii");
-            s.insert("C");
-            s.insert("cc");
-            s.insert("CC");
-            s.insert("cp");
-            s.insert("cpp");
-            s.insert("cxx");
-            s.insert("c++");
-            s.insert("C++");
-            s.insert("t++");
-            s.insert("txx");
-            // don't trace assembly to match intercept-build
-            // s.insert("s");
-            // s.insert("S");
-            // s.insert("sx");
-            // s.insert("asm");
-            s
+    /// Returns true if the file or the directory of the entry, as recorded
+    /// by the build and made absolute, matches one of the `include` globs,
+    /// if there are any, and none of the `exclude` globs.
+    fn is_included(&self, opts: &Options) -> bool {
+        let file = paths::clean(&Path::new(&self.directory).join(&self.file));
+        let file = file.to_string_lossy();
+        let matches = |globs: &[Regex]| {
+            globs
+                .iter()
+                .any(|g| g.is_match(&self.directory) || g.is_match(&file))
         };
+        (opts.include.is_empty() || matches(&opts.include)) && !matches(&opts.exclude)
+    }
+
+    /// Adds the built-in include directories of the compiler as `-isystem`
+    /// options, and its target as `--target` unless one was given.
+    fn add_builtins(&mut self, b: &Builtins) {
+        if let Some(target) = &b.target {
+            let has_target = self
+                .arguments
+                .iter()
+                .any(|a| a == "-target" || a.starts_with("--target="));
+            if !has_target {
+                self.arguments.insert(1, format!("--target={}", target));
+            }
+        }
+        for dir in &b.include_dirs {
+            self.arguments.push("-isystem".to_owned());
+            self.arguments.push(dir.clone());
+        }
+    }
+
+    /// Records the output of the command and normalizes its paths.
+    fn finish(mut self, opts: &Options) -> Self {
+        if opts.output {
+            self.output = driver::output(&self.arguments).map(|o| o.to_owned());
+        }
+        if !opts.paths.is_identity() {
+            let dir = PathBuf::from(&self.directory);
+            self.file = opts.paths.normalize(&dir, &self.file);
+            self.output = self.output.map(|o| opts.paths.normalize(&dir, &o));
+            opts.paths.normalize_args(&dir, &mut self.arguments);
+            self.directory = opts.paths.normalize_dir(&self.directory);
+        }
+        self
     }
+}
 
-    match Path::new(file).extension() {
-        Some(ext) => SRC_EXT.get(ext.to_str().unwrap()).is_some(),
-        None => false,
+fn is_source(lang: &Language, opts: &Options) -> bool {
+    match lang {
+        Language::Assembler | Language::AssemblerWithCpp => opts.assembly,
+        _ => lang.is_source(),
     }
 }
 
-#[allow(unused_must_use)]
-fn filter_args(args: Vec<String>) -> (Vec<String>, Option<String>) {
+fn filter_args(args: Vec<String>, opts: &Options) -> (Vec<String>, Option<String>) {
     lazy_static! {
-        static ref IGNORED_FLAGS: HashMap<&'static str, u8> = {
-            let mut s = HashMap::new();
-            // ignored because we will set it explicitly
-            // for compatibility with intercept build.
-            s.insert("-c", 0);
-            // preprocessor macros
-            s.insert("-MD", 0);
-            s.insert("-MMD", 0);
-            s.insert("-MG", 0);
-            s.insert("-MP", 0);
-            s.insert("-MF", 1);
-            s.insert("-MT", 1);
-            s.insert("-MQ", 1);
-            // linker options
-            s.insert("-static", 0);
-            s.insert("-shared", 0);
-            s.insert("-s", 0);
-            s.insert("-rdynamic", 0);
-            s.insert("-l", 1);
-            s.insert("-L", 1);
-            s.insert("-u", 1);
-            s.insert("-z", 1);
-            s.insert("-T", 1);
-            s.insert("-Xlinker", 1);
-            s
-        };
         static ref FILE: Regex = Regex::new(r"^[^-].+").unwrap();
     }
     let mut args = args.iter();
     let mut file = None;
+    let mut lang = LanguageSwitch::default();
     let mut filtered: Vec<String> = vec![];
     while let Some(arg) = args.next() {
-        let value = IGNORED_FLAGS.get::<str>(&arg.to_string());
-        if let Some(&n) = value {
-            (&mut args).skip(n as usize);
-        } else if arg == "-D" || arg == "-I" {
+        if let Some(n) = opts.flags.ignored(arg) {
+            for _ in 0..n {
+                args.next();
+            }
+        } else if arg == "-x" {
+            // keep the language switch since it applies to the source file
+            let operand = args.next();
+            lang.update(arg, operand.map(|s| s.as_str()));
+            filtered.push(arg.to_string());
+            filtered.extend(operand.cloned());
+        } else if lang.update(arg, None) {
             filtered.push(arg.to_string());
-            filtered.push(args.next().unwrap().to_string());
+        } else if driver::takes_operand(arg) {
+            // the operand of e.g. `-o` or `-include` is never the source
+            filtered.push(arg.to_string());
+            filtered.extend(args.next().cloned());
         } else {
-            if FILE.is_match(arg) && is_source(arg) {
+            let is_source = lang.language_of(arg).is_some_and(|l| is_source(&l, opts));
+            if FILE.is_match(arg) && is_source {
                 // chop off leading ./ to match output of intercept-build
                 let f = arg.to_string();
                 let f = if f.starts_with("./") {
@@ -131,20 +244,42 @@ fn filter_args(args: Vec<String>) -> (Vec<String>, Option<String>) {
     (filtered, file)
 }
 
-pub fn filter_execs(e: Exec) -> Option<(Exec, ToolKind)> {
+/// Drops the flags `opts.flags` ignores from a direct assembler invocation
+/// and finds its source file. Unlike compiler drivers, the assembler treats
+/// every non-option argument as a source.
+fn filter_as_args(args: Vec<String>, opts: &Options) -> Option<(Vec<String>, String)> {
     lazy_static! {
-        static ref NOT_COMPILING: HashSet<&'static str> = {
+        static ref OPERAND_FLAGS: HashSet<&'static str> = {
             let mut s = HashSet::new();
-            s.insert("-E");
-            s.insert("-cc1");
-            s.insert("-cc1as");
-            s.insert("-M");
-            s.insert("-MM");
-            s.insert("-###");
+            s.insert("-o");
+            s.insert("-I");
+            s.insert("--defsym");
+            s.insert("--MD");
             s
         };
     }
+    let mut file = None;
+    let mut iter = args.into_iter();
+    let mut filtered = iter.next().into_iter().collect::<Vec<_>>();
+    while let Some(arg) = iter.next() {
+        if let Some(n) = opts.flags.ignored(&arg) {
+            for _ in 0..n {
+                iter.next();
+            }
+        } else if OPERAND_FLAGS.contains(arg.as_str()) {
+            filtered.push(arg);
+            filtered.extend(iter.next());
+        } else {
+            if driver::is_input(&arg) && arg != "-" {
+                file = Some(arg.trim_start_matches("./").to_owned());
+            }
+            filtered.push(arg);
+        }
+    }
+    file.map(|f| (filtered, f))
+}
 
+pub fn filter_execs(e: Exec) -> Option<(Exec, ToolKind)> {
     let tk = ToolKind::from(&e);
     match tk {
         ToolKind::CCompiler(ref a) | ToolKind::CXXCompiler(ref a)
@@ -152,30 +287,537 @@ pub fn filter_execs(e: Exec) -> Option<(Exec, ToolKind)> {
         {
             Some((e, tk))
         }
+        // kept for `write_link_commands` and `write_archives`
+        ToolKind::CCompiler(CompilerAction::Link)
+        | ToolKind::CXXCompiler(CompilerAction::Link)
+        | ToolKind::Linker
+        | ToolKind::Archiver => Some((e, tk)),
+        // kept so `write_compile_commands` can include them on request
+        ToolKind::Assembler => Some((e, tk)),
         _ => None,
     }
 }
 
-pub fn write_compile_commands(v: Vec<(Exec, ToolKind)>) -> Result<()> {
-    let mut cmds = vec![];
-    for (e, t) in v {
-        let cmd = CompileCmd::try_from(e, t);
-        if cmd.is_some() {
-            cmds.push(cmd.unwrap());
+/// Sorts entries by directory, file, output and then every other field so
+/// identical builds produce identical databases, dropping exact duplicates. With `keep_duplicates`,
+/// every entry is kept and, if all of them carry a timestamp, they are
+/// listed in execution order instead.
+fn order_cmds(mut cmds: Vec<(Option<f64>, CompileCmd)>, keep_duplicates: bool) -> Vec<CompileCmd> {
+    cmds.sort_by(|(_, a), (_, b)| {
+        (
+            &a.directory,
+            &a.file,
+            &a.output,
+            &a.arguments,
+            &a.command,
+            &a.environment,
+            &a.headers,
+            &a.generated,
+            &a.target,
+        )
+            .cmp(&(
+                &b.directory,
+                &b.file,
+                &b.output,
+                &b.arguments,
+                &b.command,
+                &b.environment,
+                &b.headers,
+                &b.generated,
+                &b.target,
+            ))
+    });
+    if !keep_duplicates {
+        cmds.dedup_by(|(_, a), (_, b)| a == b);
+    } else if cmds.iter().all(|(t, _)| t.is_some()) {
+        cmds.sort_by(|(a, _), (b, _)| a.unwrap().total_cmp(&b.unwrap()));
+    }
+    cmds.into_iter().map(|(_, cmd)| cmd).collect()
+}
+
+/// A problem that didn't prevent recording the other entries.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// No entries matched the selected targets; the build compiled for
+    /// these.
+    NoTargetSelected(Vec<String>),
+    /// `program` ran without `PWD`. Its entry was recorded in `directory`,
+    /// inherited from the program that started it, or skipped if None.
+    MissingDirectory {
+        program: String,
+        directory: Option<String>,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::NoTargetSelected(targets) => {
+                write!(
+                    f,
+                    "no entries matched the selected targets; the build compiled for:"
+                )?;
+                for t in targets {
+                    write!(f, "\n    {}", t)?;
+                }
+                Ok(())
+            }
+            Warning::MissingDirectory {
+                program,
+                directory: Some(dir),
+            } => write!(
+                f,
+                "{} ran without PWD; recorded it in {}, the directory of its parent",
+                program, dir
+            ),
+            Warning::MissingDirectory {
+                program,
+                directory: None,
+            } => write!(f, "{} ran without PWD; skipped it", program),
         }
     }
+}
 
-    // Serialize it to a JSON string.
-    let json = serde_json::to_string_pretty(&cmds)?;
+fn pwd(e: &Exec) -> Option<&String> {
+    e.env.iter().find(|(k, _v)| k == "PWD").map(|(_k, v)| v)
+}
 
-    let mut file = match File::create("compile_commands.json") {
-        Ok(file) => file,
-        Err(e) => panic!("Unable to open file for writing: {}", e),
-    };
-    match file.write_all(json.as_bytes()) {
-        Ok(()) => (),
-        Err(e) => panic!("Unable to write compile_commands.json: {}", e),
+/// The working directory of the nearest program before `e` that recorded
+/// one: an earlier program of its process, or else the program its parent
+/// was running when it forked.
+fn inherited_dir(tree: &ProcessTree, e: &Exec) -> Option<String> {
+    let p = tree.get(e.pid)?;
+    let i = p.execs.iter().position(|x| x == e)?;
+    if let Some(dir) = p.execs[..i].iter().rev().find_map(pwd) {
+        return Some(dir.clone());
+    }
+    let mut child = p;
+    while let Some(p) = child.parent.and_then(|parent| tree.get(parent)) {
+        let before_fork = |x: &&Exec| match (x.timestamp, child.start) {
+            (Some(t), Some(start)) => t <= start,
+            _ => true,
+        };
+        if let Some(dir) = p.execs.iter().rev().filter(before_fork).find_map(pwd) {
+            return Some(dir.clone());
+        }
+        child = p;
+    }
+    None
+}
+
+/// The entries recorded from a build.
+#[derive(Debug, Default)]
+pub struct CompileCommands {
+    pub cmds: Vec<CompileCmd>,
+    /// The built-ins of each compiler, if `Options::builtins` is set.
+    pub builtins: BuiltinsCache,
+    pub warnings: Vec<Warning>,
+}
+
+/// Builds the entries of the compilation database from the compiler and
+/// assembler invocations among `v`.
+pub fn compile_commands(
+    v: Vec<(Exec, ToolKind)>,
+    tree: &ProcessTree,
+    opts: &Options,
+) -> CompileCommands {
+    let generated = if opts.generated {
+        generated_files(tree)
+    } else {
+        HashMap::new()
     };
+    let mut builtins = BuiltinsCache::default();
+    let mut targets = BTreeSet::new();
+    let mut timed_cmds = vec![];
+    let mut warnings = vec![];
+    for (mut e, t) in v {
+        let keep = match t {
+            ToolKind::CCompiler(CompilerAction::Compile)
+            | ToolKind::CXXCompiler(CompilerAction::Compile) => true,
+            ToolKind::Assembler => opts.assembly,
+            _ => false,
+        };
+        if !keep {
+            continue;
+        }
+        let target = target_key(&e.path, &e.args);
+        if !opts.targets.is_empty() && !is_selected(&target, &opts.targets) {
+            targets.insert(target);
+            continue;
+        }
+        if pwd(&e).is_none() {
+            let directory = inherited_dir(tree, &e);
+            e.env
+                .extend(directory.clone().map(|d| ("PWD".to_owned(), d)));
+            warnings.push(Warning::MissingDirectory {
+                program: e.path.clone(),
+                directory,
+            });
+        }
+        let timestamp = e.timestamp;
+        let compiler = match t {
+            ToolKind::CCompiler(_) | ToolKind::CXXCompiler(_) => Some(e.path.clone()),
+            _ => None,
+        };
+        let cxx = matches!(t, ToolKind::CXXCompiler(_));
+        let headers = if opts.headers || opts.generated {
+            headers_read(tree, &e)
+        } else {
+            vec![]
+        };
+        let inputs = if opts.generated {
+            generated_inputs(&generated, &e, &headers)
+        } else {
+            vec![]
+        };
+        let cmd = CompileCmd::try_from(e, t, opts);
+        if let Some(mut cmd) = cmd {
+            let normalize = |f: &String| opts.paths.normalize(Path::new("/"), f);
+            if opts.headers {
+                cmd.headers = headers.iter().map(normalize).collect();
+            }
+            cmd.generated = inputs
+                .into_iter()
+                .map(|g| Generated {
+                    file: normalize(&g.file),
+                    ..g
+                })
+                .collect();
+            if opts.target {
+                cmd.target = Some(target);
+            }
+            if let (Some(mode), Some(compiler)) = (opts.builtins, &compiler) {
+                let language = match Language::from_path(&cmd.file) {
+                    _ if cxx => "c++",
+                    Some(Language::Cxx) | Some(Language::CxxHeader) => "c++",
+                    _ => "c",
+                };
+                let b = builtins.get(compiler, language);
+                if let (BuiltinsMode::Inject, Some(b)) = (mode, b) {
+                    cmd.add_builtins(b);
+                }
+            }
+            if opts.command {
+                cmd.use_command();
+            }
+            timed_cmds.push((timestamp, cmd));
+        }
+    }
+    if timed_cmds.is_empty() && !targets.is_empty() {
+        warnings.push(Warning::NoTargetSelected(targets.into_iter().collect()));
+    }
+    CompileCommands {
+        cmds: order_cmds(timed_cmds, opts.keep_duplicates),
+        builtins,
+        warnings,
+    }
+}
+
+/// Writes `compile_commands.json`, and `compiler_builtins.json` if asked
+/// to, and returns the warnings to report.
+pub fn write_compile_commands(
+    v: Vec<(Exec, ToolKind)>,
+    tree: &ProcessTree,
+    opts: &Options,
+) -> Result<Vec<Warning>> {
+    let res = compile_commands(v, tree, opts);
+    if opts.builtins == Some(BuiltinsMode::Sidecar) {
+        write_builtins(&res.builtins)?;
+    }
+    write_json("compile_commands.json", &res.cmds)?;
+    Ok(res.warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+    use crate::tools::flags::Profile;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn mock_cmd(file: &str, output: Option<&str>) -> CompileCmd {
+        CompileCmd {
+            directory: "/src".to_owned(),
+            file: file.to_owned(),
+            command: None,
+            arguments: strings(&["cc", "-c", file]),
+            output: output.map(|o| o.to_owned()),
+            environment: BTreeMap::new(),
+            headers: vec![],
+            generated: vec![],
+            target: None,
+        }
+    }
+
+    #[test]
+    fn test_order_cmds() {
+        let cmds = vec![
+            (Some(3.0), mock_cmd("b.c", None)),
+            (Some(1.0), mock_cmd("a.c", Some("a2.o"))),
+            (Some(2.0), mock_cmd("b.c", None)),
+            (Some(0.0), mock_cmd("a.c", Some("a1.o"))),
+        ];
+        assert_eq!(
+            order_cmds(cmds.clone(), false),
+            vec![
+                mock_cmd("a.c", Some("a1.o")),
+                mock_cmd("a.c", Some("a2.o")),
+                mock_cmd("b.c", None),
+            ]
+        );
+        assert_eq!(
+            order_cmds(cmds.clone(), true),
+            vec![
+                mock_cmd("a.c", Some("a1.o")),
+                mock_cmd("a.c", Some("a2.o")),
+                mock_cmd("b.c", None),
+                mock_cmd("b.c", None),
+            ]
+        );
+
+        let mut untimed = cmds;
+        untimed[0].0 = None;
+        assert_eq!(order_cmds(untimed, true).len(), 4);
+
+        // identical entries separated by one differing only in its headers
+        let with_headers = |headers: &[&str]| CompileCmd {
+            headers: strings(headers),
+            ..mock_cmd("a.c", None)
+        };
+        let cmds = vec![
+            (None, with_headers(&["/b.h"])),
+            (None, with_headers(&["/a.h"])),
+            (None, with_headers(&["/b.h"])),
+        ];
+        assert_eq!(
+            order_cmds(cmds, false),
+            vec![with_headers(&["/a.h"]), with_headers(&["/b.h"])]
+        );
+    }
+
+    #[test]
+    fn test_filter_env() {
+        let env = vec![
+            ("PATH".to_owned(), "/usr/bin".to_owned()),
+            ("CPATH".to_owned(), "/opt/include".to_owned()),
+            ("CCACHE_DIR".to_owned(), "/tmp/ccache".to_owned()),
+            ("CPATHX".to_owned(), "x".to_owned()),
+        ];
+        let allowed = strings(DEFAULT_ENVIRONMENT);
+        let recorded = filter_env(&env, &allowed);
+        assert_eq!(
+            recorded.keys().collect::<Vec<_>>(),
+            vec!["CCACHE_DIR", "CPATH"]
+        );
+        assert!(filter_env(&env, &[]).is_empty());
+    }
 
-    Ok(())
+    #[test]
+    fn test_is_included() {
+        let mut opts = Options::default();
+        let cmd = |dir: &str, file: &str| CompileCmd {
+            directory: dir.to_owned(),
+            ..mock_cmd(file, None)
+        };
+        assert!(cmd("/p", "third_party/z.c").is_included(&opts));
+
+        let root = Path::new("/src/p");
+        opts.include = vec![paths::glob("src/**", root)];
+        opts.exclude = vec![
+            paths::glob("src/third_party/**", root),
+            paths::glob("*_test.c", root),
+        ];
+        assert!(cmd("/src/p", "src/a.c").is_included(&opts));
+        assert!(cmd("/src/p/src", "../src/b.c").is_included(&opts));
+        assert!(!cmd("/src/p", "tools/gen.c").is_included(&opts));
+        assert!(!cmd("/src/p/tools/src", "gen.c").is_included(&opts));
+        assert!(!cmd("/src/p/src/third_party/z", "z.c").is_included(&opts));
+        assert!(!cmd("/src/p", "src/lib/a_test.c").is_included(&opts));
+    }
+
+    #[test]
+    fn test_add_builtins() {
+        let b = Builtins {
+            compiler: "/usr/bin/arm-none-eabi-gcc".to_owned(),
+            language: "c".to_owned(),
+            target: Some("arm-none-eabi".to_owned()),
+            include_dirs: strings(&["/usr/lib/gcc/arm-none-eabi/12/include"]),
+            ..Default::default()
+        };
+        let mut cmd = mock_cmd("a.c", None);
+        cmd.add_builtins(&b);
+        assert_eq!(
+            cmd.arguments,
+            strings(&[
+                "cc",
+                "--target=arm-none-eabi",
+                "-c",
+                "a.c",
+                "-isystem",
+                "/usr/lib/gcc/arm-none-eabi/12/include"
+            ])
+        );
+
+        let mut cmd = mock_cmd("a.c", None);
+        cmd.arguments.insert(1, "--target=armv7m".to_owned());
+        cmd.add_builtins(&b);
+        assert_eq!(cmd.arguments[1], "--target=armv7m");
+        assert_eq!(cmd.arguments[2], "-c");
+    }
+
+    #[test]
+    fn test_filter_args() {
+        let args = strings(&[
+            "gcc", "-MD", "-MF", "foo.d", "-I", "inc", "-o", "foo.o", "-c", "./foo.c",
+        ]);
+        let opts = Options::default();
+        assert_eq!(
+            filter_args(args.clone(), &opts),
+            (
+                strings(&["gcc", "-I", "inc", "-o", "foo.o", "foo.c"]),
+                Some("foo.c".to_owned())
+            )
+        );
+
+        let opts = Options {
+            flags: FilterPolicy::new(Profile::Raw),
+            ..Default::default()
+        };
+        let mut expected = args.clone();
+        expected[9] = "foo.c".to_owned();
+        assert_eq!(
+            filter_args(args, &opts),
+            (expected, Some("foo.c".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_keep_compile_flag() {
+        let mut e = Exec::mock("/usr/bin/gcc", &["-c", "a.c"]);
+        e.env.push(("PWD".to_owned(), "/src".to_owned()));
+        let mut opts = Options::default();
+        opts.flags.keep("-c");
+        let t = ToolKind::CCompiler(CompilerAction::Compile);
+        let cmd = CompileCmd::try_from(e, t, &opts).unwrap();
+        assert_eq!(cmd.arguments, strings(&["cc", "-c", "a.c"]));
+    }
+
+    #[test]
+    fn test_compile_commands() {
+        let mut e = Exec::mock("/usr/bin/gcc", &["-c", "a.c", "-o", "a.o"]);
+        e.env.push(("PWD".to_owned(), "/src".to_owned()));
+        let tree = ProcessTree::from(vec![(1, vec![Event::Exec(e)])]);
+        let execs = || tree.execs().cloned().filter_map(filter_execs).collect();
+
+        let res = compile_commands(execs(), &tree, &Options::default());
+        assert_eq!(res.cmds.len(), 1);
+        assert_eq!(res.cmds[0].file(), "a.c");
+        assert_eq!(res.cmds[0].output(), None);
+        assert!(res.warnings.is_empty());
+
+        let opts = Options {
+            targets: strings(&["arm-none-eabi"]),
+            ..Default::default()
+        };
+        let res = compile_commands(execs(), &tree, &opts);
+        assert!(res.cmds.is_empty());
+        assert_eq!(
+            res.warnings,
+            vec![Warning::NoTargetSelected(strings(&["host"]))]
+        );
+    }
+
+    #[test]
+    fn test_missing_directory() {
+        let mut make = Exec::mock("/usr/bin/make", &[]);
+        make.env.push(("PWD".to_owned(), "/src".to_owned()));
+        let gcc = Exec::mock("/usr/bin/gcc", &["-c", "a.c"]);
+        let fork = Event::Fork {
+            child: 2,
+            timestamp: None,
+        };
+        let tree = ProcessTree::from(vec![
+            (1, vec![Event::Exec(make), fork]),
+            (2, vec![Event::Exec(gcc.clone())]),
+        ]);
+        let execs = tree.execs().cloned().filter_map(filter_execs).collect();
+        let res = compile_commands(execs, &tree, &Options::default());
+        assert_eq!(res.cmds.len(), 1);
+        assert_eq!(res.cmds[0].directory(), "/src");
+        assert_eq!(
+            res.warnings,
+            vec![Warning::MissingDirectory {
+                program: "/usr/bin/gcc".to_owned(),
+                directory: Some("/src".to_owned()),
+            }]
+        );
+
+        let tree = ProcessTree::from(vec![(2, vec![Event::Exec(gcc)])]);
+        let execs = tree.execs().cloned().filter_map(filter_execs).collect();
+        let res = compile_commands(execs, &tree, &Options::default());
+        assert!(res.cmds.is_empty());
+        assert_eq!(
+            res.warnings[0].to_string(),
+            "/usr/bin/gcc ran without PWD; skipped it"
+        );
+    }
+
+    #[test]
+    fn test_filter_as_args() {
+        let cases: &[(&[&str], Option<&str>)] = &[
+            (&["as", "-o", "foo.o", "./foo.s"], Some("foo.s")),
+            (&["as", "foo.s", "-o", "bar.s"], Some("foo.s")),
+            (&["as", "-I", "inc.s", "foo.s"], Some("foo.s")),
+            (&["as", "--defsym", "X=1", "foo.s"], Some("foo.s")),
+            (&["as", "--MD", "foo.d", "foo.s"], Some("foo.s")),
+            // the assembler concatenates its inputs; record the last one
+            (&["as", "a.s", "b.s", "-o", "ab.o"], Some("b.s")),
+            (&["as", "-o", "foo.o", "-"], None),
+            (&["as", "--version"], None),
+        ];
+        for (args, file) in cases {
+            let args = strings(args);
+            assert_eq!(
+                filter_as_args(args.clone(), &Options::default()),
+                file.map(|f| (args, f.to_owned())),
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_as_flags() {
+        let mut opts = Options::default();
+        opts.flags.strip("-g".parse().unwrap());
+        opts.flags.strip("--MD:1".parse().unwrap());
+        let args = strings(&["as", "-g", "--MD", "foo.d", "-o", "foo.o", "foo.s"]);
+        assert_eq!(
+            filter_as_args(args, &opts),
+            Some((strings(&["as", "-o", "foo.o", "foo.s"]), "foo.s".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_assembler_tmpdir() {
+        let opts = Options {
+            assembly: true,
+            ..Default::default()
+        };
+        let exec = |tmp_dir: Option<&str>, file: &str| {
+            let mut e = Exec::mock("/usr/bin/as", &["-o", "foo.o", file]);
+            e.env.push(("PWD".to_owned(), "/src".to_owned()));
+            e.env
+                .extend(tmp_dir.map(|d| ("TMPDIR".to_owned(), d.to_owned())));
+            CompileCmd::try_from(e, ToolKind::Assembler, &opts).map(|cmd| cmd.file)
+        };
+        assert_eq!(exec(None, "foo.s"), Some("foo.s".to_owned()));
+        assert_eq!(exec(None, "/tmp/ccX1.s"), None);
+        assert_eq!(exec(Some("/scratch"), "/scratch/ccX1.s"), None);
+        assert_eq!(
+            exec(Some("/scratch"), "/tmp/foo.s"),
+            Some("/tmp/foo.s".to_owned())
+        );
+    }
 }
@@ -1,28 +1,164 @@
+//! The entries of a JSON compilation database.
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct CompileCmd {
     /// The working directory of the compilation. All paths specified in the command
     /// or file fields must be either absolute or relative to this directory.
-    directory: String,
+    pub(crate) directory: String,
     /// The main translation unit source processed by this compilation step. This is
     /// used by tools as the key into the compilation database. There can be multiple
     /// command objects for the same file, for example if the same source file is compiled
     /// with different configurations.
-    file: String,
+    pub(crate) file: String,
     /// The compile command executed. After JSON unescaping, this must be a valid command
     /// to rerun the exact compilation step for the translation unit in the environment
     /// the build system uses. Parameters use shell quoting and shell escaping of quotes,
     /// with ‘"’ and ‘\’ being the only special characters. Shell expansion is not supported.
     #[serde(skip_serializing_if = "Option::is_none")]
-    command: Option<String>,
+    pub(crate) command: Option<String>,
     /// The compile command executed as list of strings. Either arguments or command is required.
-    #[serde(default)]
-    arguments: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) arguments: Vec<String>,
     /// The name of the output created by this compilation step. This field is optional. It can
     /// be used to distinguish different processing modes of the same input file.
     #[serde(skip_serializing_if = "Option::is_none")]
-    output: Option<String>,
+    pub(crate) output: Option<String>,
+    /// Extension: the environment variables affecting the compilation, such as
+    /// `CPATH`, which the command must be rerun with.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) environment: BTreeMap<String, String>,
+    /// Extension: the headers read by the compilation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) headers: Vec<String>,
+    /// Extension: the source and headers of the compilation that were generated
+    /// by the build, which must not be edited and must exist before analysis.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) generated: Vec<Generated>,
+    /// Extension: the target and configuration the source was compiled
+    /// for, like `arm-none-eabi -march=armv7e-m` or `host -m32`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) target: Option<String>,
+}
+
+/// A file generated by an earlier step of the build.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Generated {
+    pub file: String,
+    /// The program that wrote the file.
+    pub generator: String,
+}
+
+impl CompileCmd {
+    /// Replaces `arguments` with the equivalent `command` string.
+    pub fn use_command(&mut self) {
+        self.command = Some(render_command(&self.arguments));
+        self.arguments.clear();
+    }
+
+    pub fn directory(&self) -> &str {
+        &self.directory
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// The arguments of the command, which must have been normalized if it
+    /// was read in the `command` form.
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// The `command` string, unless the entry is in the `arguments` form.
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    /// The environment variables the command must be rerun with.
+    pub fn environment(&self) -> &BTreeMap<String, String> {
+        &self.environment
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn generated(&self) -> &[Generated] {
+        &self.generated
+    }
+
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Replaces `command` with the equivalent `arguments` so entries can be
+    /// compared regardless of which form they were written in. `arguments`
+    /// take precedence if an entry has both.
+    pub fn normalize(&mut self) {
+        if let Some(command) = self.command.take() {
+            if self.arguments.is_empty() {
+                self.arguments = split_command(&command);
+            }
+        }
+    }
+}
+
+/// Joins `args` into a `command` string. Arguments containing whitespace
+/// are double quoted; `"` and `\` are escaped with a backslash.
+pub fn render_command(args: &[String]) -> String {
+    args.iter()
+        .map(|a| {
+            let escaped = a.replace('\\', "\\\\").replace('"', "\\\"");
+            if a.is_empty() || a.chars().any(char::is_whitespace) {
+                format!("\"{}\"", escaped)
+            } else {
+                escaped
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Splits a `command` string into arguments. The inverse of `render_command`.
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    // distinguishes an empty quoted argument from no argument at all
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                arg.extend(chars.next());
+                in_arg = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
 }
 
 impl Hash for CompileCmd {
@@ -32,7 +168,39 @@ impl Hash for CompileCmd {
         self.command.hash(state);
         self.arguments.hash(state);
         self.output.hash(state);
+        self.environment.hash(state);
+        self.headers.hash(state);
+        self.generated.hash(state);
+        self.target.hash(state);
     }
 }
 
 impl Eq for CompileCmd {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_command_quoting() {
+        let args = strings(&["cc", "-DSTR=\"a b\"", "-DPATH=C:\\dir", "", "foo bar.c"]);
+        let command = render_command(&args);
+        assert_eq!(
+            command,
+            r#"cc "-DSTR=\"a b\"" -DPATH=C:\\dir "" "foo bar.c""#
+        );
+        assert_eq!(split_command(&command), args);
+        assert_eq!(
+            split_command("  cc  -c\tfoo.c "),
+            strings(&["cc", "-c", "foo.c"])
+        );
+        assert_eq!(
+            split_command(r#"cc -D"FOO=1" x\ y.c"#),
+            strings(&["cc", "-DFOO=1", "x y.c"])
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Graphviz rendering of the process tree and build graph.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{write_output, Result};
+use crate::process::ProcessTree;
+use crate::tools::graph::{ArtifactKind, BuildGraph};
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quotes `s` as a DOT string.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+fn shape(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Source => "note",
+        ArtifactKind::Object => "ellipse",
+        ArtifactKind::Archive => "folder",
+        ArtifactKind::SharedLibrary => "component",
+        ArtifactKind::Executable => "box3d",
+    }
+}
+
+fn processes(tree: &ProcessTree, out: &mut Vec<String>) {
+    for p in tree.processes() {
+        let color = match p.retcode {
+            Some(r) if r != 0 => ", color=red",
+            _ => "",
+        };
+        out.push(format!(
+            "        p{} [label=\"{}\\n{}\"{}];",
+            p.pid,
+            escape(tree.name(p.pid)),
+            p.pid,
+            color
+        ));
+    }
+    for p in tree.processes() {
+        for child in &p.children {
+            if tree.get(*child).is_some() {
+                out.push(format!("        p{} -> p{};", p.pid, child));
+            }
+        }
+    }
+}
+
+/// Merges processes running the same program into a single node, with
+/// edges labelled by the number of parent/child pairs.
+fn collapsed_processes(tree: &ProcessTree, out: &mut Vec<String>) {
+    let mut counts = BTreeMap::new();
+    let mut edges = BTreeMap::new();
+    for p in tree.processes() {
+        *counts.entry(tree.name(p.pid)).or_insert(0) += 1;
+        for child in p.children.iter().filter(|c| tree.get(**c).is_some()) {
+            *edges
+                .entry((tree.name(p.pid), tree.name(*child)))
+                .or_insert(0) += 1;
+        }
+    }
+    let ids = counts
+        .keys()
+        .enumerate()
+        .map(|(id, name)| (*name, id))
+        .collect::<BTreeMap<_, _>>();
+    for (name, count) in &counts {
+        let label = format!("{} ({})", name, count);
+        out.push(format!("        t{} [label={}];", ids[name], quote(&label)));
+    }
+    for ((from, to), count) in &edges {
+        out.push(format!(
+            "        t{} -> t{} [label=\"{}\"];",
+            ids[from], ids[to], count
+        ));
+    }
+}
+
+fn artifacts(g: &BuildGraph, out: &mut Vec<String>) {
+    for (id, n) in g.nodes.iter().enumerate() {
+        let name = Path::new(&n.path)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or(&n.path);
+        out.push(format!(
+            "        a{} [label={}, tooltip={}, shape={}];",
+            id,
+            quote(name),
+            quote(&n.path),
+            shape(n.kind)
+        ));
+    }
+    for (from, to) in &g.edges {
+        out.push(format!("        a{} -> a{};", from, to));
+    }
+}
+
+/// Renders the process tree and the build graph as two clusters of a
+/// DOT digraph. With `collapse`, processes are merged by program name.
+pub fn render(tree: &ProcessTree, g: &BuildGraph, collapse: bool) -> String {
+    let mut out = vec![
+        "digraph build {".to_owned(),
+        "    rankdir=LR;".to_owned(),
+        "    subgraph cluster_processes {".to_owned(),
+        "        label=\"processes\";".to_owned(),
+        "        node [shape=box];".to_owned(),
+    ];
+    if collapse {
+        collapsed_processes(tree, &mut out);
+    } else {
+        processes(tree, &mut out);
+    }
+    out.push("    }".to_owned());
+    out.push("    subgraph cluster_artifacts {".to_owned());
+    out.push("        label=\"artifacts\";".to_owned());
+    artifacts(g, &mut out);
+    out.push("    }".to_owned());
+    out.push("}".to_owned());
+    out.push(String::new());
+    out.join("\n")
+}
+
+pub fn write_dot(tree: &ProcessTree, g: &BuildGraph, collapse: bool) -> Result<()> {
+    write_output("build_graph.dot", render(tree, g, collapse).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+    use crate::tools::ToolKind;
+    use crate::Exec;
+
+    #[test]
+    fn test_render() {
+        let exec = |path: &str, args: &[&str]| {
+            let mut e = Exec::mock(path, args);
+            e.env.push(("PWD".to_owned(), "/build".to_owned()));
+            e
+        };
+        let fork = |child| Event::Fork {
+            child,
+            timestamp: None,
+        };
+        let cc = exec("/usr/bin/cc", &["-c", "a.c"]);
+        let tree = ProcessTree::from(vec![
+            (
+                1,
+                vec![Event::Exec(exec("/usr/bin/make", &[])), fork(2), fork(3)],
+            ),
+            (2, vec![Event::Exec(cc.clone())]),
+            (
+                3,
+                vec![
+                    Event::Exec(exec("/usr/bin/cc", &["-c", "b.c"])),
+                    Event::Exit {
+                        retcode: Some(1),
+                        timestamp: None,
+                    },
+                ],
+            ),
+        ]);
+        let g = BuildGraph::from(&[(cc.clone(), ToolKind::from(&cc))]);
+
+        let dot = render(&tree, &g, false);
+        assert!(dot.contains("p1 [label=\"make\\n1\"];"));
+        assert!(dot.contains("p1 -> p2;"));
+        assert!(dot.contains("p3 [label=\"cc\\n3\", color=red];"));
+        assert!(dot.contains("a0 [label=\"a.c\", tooltip=\"/build/a.c\", shape=note];"));
+        assert!(dot.contains("a0 -> a1;"));
+
+        let dot = render(&tree, &g, true);
+        assert!(dot.contains("t0 [label=\"cc (2)\"];"));
+        assert!(dot.contains("t1 -> t0 [label=\"2\"];"));
+    }
+}
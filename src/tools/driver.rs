@@ -0,0 +1,365 @@
+//! Helpers for walking GCC/Clang compiler driver command lines.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// The language of a driver input, as named by `-x <language>` or implied
+/// by the input's file extension.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Language {
+    C,
+    Cxx,
+    ObjC,
+    ObjCxx,
+    CPreprocessed,
+    CxxPreprocessed,
+    ObjCPreprocessed,
+    ObjCxxPreprocessed,
+    CHeader,
+    CxxHeader,
+    ObjCHeader,
+    ObjCxxHeader,
+    Assembler,
+    AssemblerWithCpp,
+    /// Any other language accepted by `-x`, e.g. `f95` or `cuda`.
+    Other(String),
+}
+
+impl Language {
+    /// Parses the operand of `-x`. Returns `None` for `-x none`, which
+    /// reverts to deciding the language by file extension.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let lang = match name {
+            "none" => return None,
+            "c" => Language::C,
+            "c++" => Language::Cxx,
+            "objective-c" => Language::ObjC,
+            "objective-c++" => Language::ObjCxx,
+            "cpp-output" => Language::CPreprocessed,
+            "c++-cpp-output" => Language::CxxPreprocessed,
+            "objective-c-cpp-output" => Language::ObjCPreprocessed,
+            "objective-c++-cpp-output" => Language::ObjCxxPreprocessed,
+            "c-header" => Language::CHeader,
+            "c++-header" => Language::CxxHeader,
+            "objective-c-header" => Language::ObjCHeader,
+            "objective-c++-header" => Language::ObjCxxHeader,
+            "assembler" => Language::Assembler,
+            "assembler-with-cpp" => Language::AssemblerWithCpp,
+            other => Language::Other(other.to_owned()),
+        };
+        Some(lang)
+    }
+
+    /// Infers the language of `file` from its extension the way the GCC
+    /// driver does. Returns `None` for files the driver passes to the
+    /// linker, such as objects and archives.
+    pub fn from_path(file: &str) -> Option<Self> {
+        lazy_static! {
+            static ref EXT_LANG: HashMap<&'static str, Language> = {
+                let mut m = HashMap::new();
+                m.insert("c", Language::C);
+                m.insert("i", Language::CPreprocessed);
+                m.insert("ii", Language::CxxPreprocessed);
+                m.insert("m", Language::ObjC);
+                m.insert("mi", Language::ObjCPreprocessed);
+                m.insert("mm", Language::ObjCxx);
+                m.insert("M", Language::ObjCxx);
+                m.insert("mii", Language::ObjCxxPreprocessed);
+                m.insert("h", Language::CHeader);
+                m.insert("hh", Language::CxxHeader);
+                m.insert("H", Language::CxxHeader);
+                m.insert("hp", Language::CxxHeader);
+                m.insert("hxx", Language::CxxHeader);
+                m.insert("hpp", Language::CxxHeader);
+                m.insert("HPP", Language::CxxHeader);
+                m.insert("h++", Language::CxxHeader);
+                m.insert("tcc", Language::CxxHeader);
+                m.insert("C", Language::Cxx);
+                m.insert("cc", Language::Cxx);
+                m.insert("CC", Language::Cxx);
+                m.insert("cp", Language::Cxx);
+                m.insert("cpp", Language::Cxx);
+                m.insert("CPP", Language::Cxx);
+                m.insert("cxx", Language::Cxx);
+                m.insert("c++", Language::Cxx);
+                m.insert("C++", Language::Cxx);
+                m.insert("t++", Language::Cxx);
+                m.insert("txx", Language::Cxx);
+                m.insert("s", Language::Assembler);
+                m.insert("asm", Language::Assembler);
+                m.insert("S", Language::AssemblerWithCpp);
+                m.insert("sx", Language::AssemblerWithCpp);
+                m
+            };
+        }
+        let ext = Path::new(file).extension()?.to_str()?;
+        EXT_LANG.get(ext).cloned()
+    }
+
+    /// Returns true for C-family translation units that produce an object
+    /// file when compiled with `-c`.
+    pub fn is_source(&self) -> bool {
+        matches!(
+            self,
+            Language::C
+                | Language::Cxx
+                | Language::ObjC
+                | Language::ObjCxx
+                | Language::CPreprocessed
+                | Language::CxxPreprocessed
+                | Language::ObjCPreprocessed
+                | Language::ObjCxxPreprocessed
+        )
+    }
+
+    /// Returns true for headers, which the driver precompiles rather than
+    /// compiling to an object file.
+    pub fn is_header(&self) -> bool {
+        matches!(
+            self,
+            Language::CHeader | Language::CxxHeader | Language::ObjCHeader | Language::ObjCxxHeader
+        )
+    }
+}
+
+/// Tracks the language selected by `-x` while walking a command line. GCC
+/// and Clang apply `-x` to every input that follows it, up to the next
+/// `-x`; `-x none` reverts to inferring the language from the extension.
+#[derive(Debug, Default)]
+pub struct LanguageSwitch {
+    current: Option<Language>,
+}
+
+impl LanguageSwitch {
+    /// Updates the selected language if `arg` is `-x <language>` or the
+    /// attached form `-x<language>`. Returns true if `arg` was a `-x` flag.
+    pub fn update(&mut self, arg: &str, operand: Option<&str>) -> bool {
+        if arg == "-x" {
+            self.current = operand.and_then(Language::from_name);
+        } else if let Some(name) = arg.strip_prefix("-x") {
+            self.current = Language::from_name(name);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// The language of input `file` at the current position.
+    pub fn language_of(&self, file: &str) -> Option<Language> {
+        if file == "-" {
+            return self.current.clone();
+        }
+        self.current.clone().or_else(|| Language::from_path(file))
+    }
+}
+
+/// A file operand of a compiler driver invocation.
+#[derive(Debug, PartialEq)]
+pub struct Input<'a> {
+    pub path: &'a str,
+    /// `None` if the driver passes the file to the linker as-is.
+    pub language: Option<Language>,
+}
+
+/// Returns the input files of a driver invocation, each attributed the
+/// language given by the closest preceding `-x` or its extension.
+pub fn inputs(args: &[String]) -> Vec<Input<'_>> {
+    let mut lang = LanguageSwitch::default();
+    let mut res = vec![];
+    for_each_arg(args, |a, operand| {
+        if !lang.update(a, operand) && is_input(a) {
+            res.push(Input {
+                path: a,
+                language: lang.language_of(a),
+            });
+        }
+    });
+    res
+}
+
+/// Returns the operand of the last `-o` option, if any.
+pub fn output(args: &[String]) -> Option<&str> {
+    let mut res = None;
+    for_each_arg(args, |a, operand| {
+        if a == "-o" {
+            res = operand;
+        } else if let Some(o) = attached_output(a) {
+            res = Some(o);
+        }
+    });
+    res
+}
+
+/// Returns the path of an `-o<path>` argument. Other options starting with
+/// `-o`, like clang's `-objcmt-*` and `-object-file-name=`, return None.
+pub fn attached_output(arg: &str) -> Option<&str> {
+    const O_OPTIONS: &[&str] = &["-objc", "-object"];
+    if O_OPTIONS.iter().any(|o| arg.starts_with(o)) {
+        return None;
+    }
+    arg.strip_prefix("-o").filter(|o| !o.is_empty())
+}
+
+/// Returns true if the driver flag `arg` consumes the next argument as
+/// its operand, e.g. `-o foo.o` or `-MF foo.d`. Attached forms such as
+/// `-ofoo.o` or `-DFOO` are single arguments and return false.
+pub fn takes_operand(arg: &str) -> bool {
+    lazy_static! {
+        static ref OPERAND_FLAGS: HashSet<&'static str> = {
+            let mut s = HashSet::new();
+            // output and language selection
+            s.insert("-o");
+            s.insert("-x");
+            s.insert("-aux-info");
+            // preprocessor
+            s.insert("-D");
+            s.insert("-U");
+            s.insert("-I");
+            s.insert("-include");
+            s.insert("-imacros");
+            s.insert("-isystem");
+            s.insert("-iquote");
+            s.insert("-idirafter");
+            s.insert("-iprefix");
+            s.insert("-iwithprefix");
+            s.insert("-iwithprefixbefore");
+            s.insert("-isysroot");
+            s.insert("-imultilib");
+            s.insert("-MF");
+            s.insert("-MT");
+            s.insert("-MQ");
+            // target selection
+            s.insert("-target");
+            s.insert("-arch");
+            s.insert("--sysroot");
+            s.insert("-gcc-toolchain");
+            // pass-through to subprocesses
+            s.insert("-Xassembler");
+            s.insert("-Xpreprocessor");
+            s.insert("-Xlinker");
+            s.insert("-Xclang");
+            s.insert("-Xanalyzer");
+            s.insert("-mllvm");
+            s.insert("--param");
+            // linker
+            s.insert("-l");
+            s.insert("-L");
+            s.insert("-u");
+            s.insert("-z");
+            s.insert("-T");
+            s.insert("-e");
+            s.insert("-F");
+            s.insert("-framework");
+            s
+        };
+    }
+    OPERAND_FLAGS.contains(arg)
+}
+
+/// Calls `f` on every argument after `argv[0]` that is not the operand of
+/// a preceding flag. The second parameter passed to `f` is the operand of
+/// `arg` when `arg` takes one.
+pub fn for_each_arg<'a, F>(args: &'a [String], mut f: F)
+where
+    F: FnMut(&'a str, Option<&'a str>),
+{
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let operand = if takes_operand(arg) {
+            iter.next().map(|s| s.as_str())
+        } else {
+            None
+        };
+        f(arg, operand);
+    }
+}
+
+/// Returns true if `arg` names an input file rather than a flag. A lone
+/// `-` denotes standard input.
+pub fn is_input(arg: &str) -> bool {
+    arg == "-" || !arg.starts_with('-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_for_each_arg() {
+        let args = strings(&["cc", "-o", "foo.o", "-DFOO", "-I", "inc", "-c", "foo.c"]);
+        let mut seen = vec![];
+        for_each_arg(&args, |a, op| seen.push((a, op)));
+        assert_eq!(
+            seen,
+            vec![
+                ("-o", Some("foo.o")),
+                ("-DFOO", None),
+                ("-I", Some("inc")),
+                ("-c", None),
+                ("foo.c", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inputs() {
+        let args = strings(&[
+            "cc", "a.c", "-x", "c++", "b.inc", "-", "-x", "none", "c.cc", "d.o", "-xc", "e.def",
+        ]);
+        let langs = inputs(&args)
+            .into_iter()
+            .map(|i| (i.path, i.language))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            langs,
+            vec![
+                ("a.c", Some(Language::C)),
+                ("b.inc", Some(Language::Cxx)),
+                ("-", Some(Language::Cxx)),
+                ("c.cc", Some(Language::Cxx)),
+                ("d.o", None),
+                ("e.def", Some(Language::C)),
+            ]
+        );
+
+        let args = strings(&["cc", "-x", "c-header", "-c", "foo.h", "-o", "foo.h.gch"]);
+        assert_eq!(
+            inputs(&args),
+            vec![Input {
+                path: "foo.h",
+                language: Some(Language::CHeader)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_output() {
+        assert_eq!(output(&strings(&["cc", "-c", "foo.c"])), None);
+        assert_eq!(
+            output(&strings(&["cc", "-o", "a.o", "-c", "a.c"])),
+            Some("a.o")
+        );
+        assert_eq!(output(&strings(&["cc", "-oa.o", "-c", "a.c"])), Some("a.o"));
+        assert_eq!(
+            output(&strings(&[
+                "clang",
+                "-o",
+                "a.o",
+                "-objcmt-migrate-literals",
+                "a.m"
+            ])),
+            Some("a.o")
+        );
+        assert_eq!(output(&strings(&["clang", "-object-file-name=x.o"])), None);
+    }
+
+    #[test]
+    fn test_is_input() {
+        assert!(is_input("foo.c"));
+        assert!(is_input("-"));
+        assert!(!is_input("-c"));
+    }
+}
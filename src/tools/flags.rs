@@ -0,0 +1,221 @@
+//! Policies deciding which compiler flags are dropped from recorded commands.
+use std::str::FromStr;
+
+/// Removes a flag, and the given number of operands following it, from
+/// recorded commands. A pattern ending in `*` matches any flag with that
+/// prefix, e.g. `-fsanitize*` matches `-fsanitize=address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlagRule {
+    pattern: String,
+    operands: usize,
+}
+
+impl FlagRule {
+    pub fn new(pattern: &str, operands: usize) -> Self {
+        FlagRule {
+            pattern: pattern.to_owned(),
+            operands,
+        }
+    }
+
+    fn matches(&self, arg: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            Some(prefix) => arg.starts_with(prefix),
+            None => arg == self.pattern,
+        }
+    }
+}
+
+impl FromStr for FlagRule {
+    type Err = String;
+
+    /// Parses `FLAG` or `FLAG:N`, where `N` is the number of operands that
+    /// follow the flag as separate arguments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, operands) = match s.rfind(':') {
+            Some(i) => {
+                let n = s[i + 1..]
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid operand count in flag rule: {}", s))?;
+                (&s[..i], n)
+            }
+            None => (s, 0),
+        };
+        if !pattern.starts_with('-') {
+            return Err(format!("flag rule must start with '-': {}", s));
+        }
+        Ok(FlagRule::new(pattern, operands))
+    }
+}
+
+/// Named sets of flag rules for common consumers of compilation databases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    /// Record commands exactly as executed.
+    Raw,
+    /// Drop dependency generation and linker flags and normalize the
+    /// compiler name like intercept-build does.
+    InterceptBuild,
+    /// Like `InterceptBuild`, but also drop flags that only produce noise
+    /// in editors, such as `-Werror` and sanitizer options.
+    Clangd,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Profile::Raw),
+            "intercept-build" => Ok(Profile::InterceptBuild),
+            "clangd" => Ok(Profile::Clangd),
+            _ => Err(format!("unknown flag filtering profile: {}", s)),
+        }
+    }
+}
+
+impl Profile {
+    pub const NAMES: &'static [&'static str] = &["raw", "intercept-build", "clangd"];
+
+    fn rules(self) -> Vec<FlagRule> {
+        let intercept_build = &[
+            // ignored because we will set it explicitly
+            // for compatibility with intercept build.
+            ("-c", 0),
+            // preprocessor macros
+            ("-MD", 0),
+            ("-MMD", 0),
+            ("-MG", 0),
+            ("-MP", 0),
+            ("-MF", 1),
+            ("-MT", 1),
+            ("-MQ", 1),
+            // linker options
+            ("-static", 0),
+            ("-shared", 0),
+            ("-s", 0),
+            ("-rdynamic", 0),
+            ("-l", 1),
+            ("-L", 1),
+            ("-u", 1),
+            ("-z", 1),
+            ("-T", 1),
+            ("-Xlinker", 1),
+        ];
+        let clangd = &[
+            ("-Werror*", 0),
+            ("-pedantic-errors", 0),
+            ("-fdiagnostics-color*", 0),
+            ("-fcolor-diagnostics", 0),
+            ("-fno-color-diagnostics", 0),
+            ("-fsanitize*", 0),
+            ("-fno-sanitize*", 0),
+            ("-pipe", 0),
+            ("-save-temps*", 0),
+        ];
+
+        let rules: &[&[(&str, usize)]] = match self {
+            Profile::Raw => &[],
+            Profile::InterceptBuild => &[intercept_build],
+            Profile::Clangd => &[intercept_build, clangd],
+        };
+        rules
+            .iter()
+            .flat_map(|r| r.iter())
+            .map(|&(p, n)| FlagRule::new(p, n))
+            .collect()
+    }
+}
+
+/// The flag rules applied to compiler command lines: those of a profile
+/// plus user-defined additions and removals.
+#[derive(Debug, Clone)]
+pub struct FilterPolicy {
+    profile: Profile,
+    rules: Vec<FlagRule>,
+    kept: Vec<String>,
+}
+
+impl Default for FilterPolicy {
+    fn default() -> Self {
+        FilterPolicy::new(Profile::InterceptBuild)
+    }
+}
+
+impl FilterPolicy {
+    pub fn new(profile: Profile) -> Self {
+        FilterPolicy {
+            profile,
+            rules: profile.rules(),
+            kept: vec![],
+        }
+    }
+
+    /// Also drop flags matching `rule`.
+    pub fn strip(&mut self, rule: FlagRule) {
+        self.rules.push(rule);
+    }
+
+    /// Keep `flag` even if a rule drops it. Other flags the rule matches
+    /// are still dropped, e.g. keeping `-Werror` doesn't keep `-Werror=format`.
+    pub fn keep(&mut self, flag: &str) {
+        self.kept.push(flag.to_owned());
+    }
+
+    /// Returns the number of operands to drop along with `arg` if it
+    /// matches a rule, or `None` if `arg` should be kept.
+    pub fn ignored(&self, arg: &str) -> Option<usize> {
+        if self.kept.iter().any(|k| k == arg) {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|r| r.matches(arg))
+            .map(|r| r.operands)
+    }
+
+    /// Returns true if commands are rewritten to start with `cc -c` or
+    /// `c++ -c` rather than the compiler as invoked.
+    pub fn normalizes_driver(&self) -> bool {
+        self.profile != Profile::Raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_rule_from_str() {
+        assert_eq!("-MF:1".parse(), Ok(FlagRule::new("-MF", 1)));
+        assert_eq!("-Werror*".parse(), Ok(FlagRule::new("-Werror*", 0)));
+        assert!("-MF:x".parse::<FlagRule>().is_err());
+        assert!("Werror".parse::<FlagRule>().is_err());
+    }
+
+    #[test]
+    fn test_filter_policy() {
+        let raw = FilterPolicy::new(Profile::Raw);
+        assert_eq!(raw.ignored("-MD"), None);
+        assert!(!raw.normalizes_driver());
+
+        let mut ib = FilterPolicy::default();
+        assert_eq!(ib.ignored("-MF"), Some(1));
+        assert_eq!(ib.ignored("-Werror"), None);
+        ib.keep("-MF");
+        assert_eq!(ib.ignored("-MF"), None);
+        ib.strip("-Werror*".parse().unwrap());
+        assert_eq!(ib.ignored("-Werror=format"), Some(0));
+
+        let clangd = FilterPolicy::new(Profile::Clangd);
+        assert_eq!(clangd.ignored("-fsanitize=address"), Some(0));
+        assert_eq!(clangd.ignored("-fdiagnostics-color=always"), Some(0));
+        assert_eq!(clangd.ignored("-L"), Some(1));
+        assert_eq!(clangd.ignored("-O2"), None);
+
+        let mut clangd = FilterPolicy::new(Profile::Clangd);
+        clangd.keep("-Werror");
+        assert_eq!(clangd.ignored("-Werror"), None);
+        assert_eq!(clangd.ignored("-Werror=format"), Some(0));
+    }
+}
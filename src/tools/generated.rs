@@ -0,0 +1,194 @@
+//! Detection of files generated by the build, such as parser sources,
+//! configuration headers and protobuf stubs.
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::process::ProcessTree;
+use crate::tools::ccmd::Generated;
+use crate::tools::{driver, paths};
+use crate::Exec;
+
+/// The program that last wrote a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Writer {
+    /// The file name of the program, like `bison`.
+    pub generator: String,
+    pub timestamp: Option<f64>,
+}
+
+fn name(e: &Exec) -> String {
+    Path::new(&e.path)
+        .file_name()
+        .map_or_else(|| e.path.clone(), |f| f.to_string_lossy().into_owned())
+}
+
+fn absolute(e: Option<&Exec>, path: &str) -> String {
+    let dir = e
+        .and_then(|e| e.env.iter().find(|(k, _v)| k == "PWD"))
+        .map_or("/", |(_k, v)| v.as_str());
+    paths::clean(&Path::new(dir).join(path))
+        .to_string_lossy()
+        .into_owned()
+}
+
+enum Op {
+    Write(String),
+    Rename(String, String),
+}
+
+/// The files written during the build, by absolute path. Files moved into
+/// place, as many generators do to update their output atomically, keep
+/// the program that wrote them.
+pub fn generated_files(tree: &ProcessTree) -> HashMap<String, Writer> {
+    let mut ops = vec![];
+    for p in tree.processes() {
+        for o in p.opened.iter().filter(|o| o.write) {
+            let program = tree.program(p.pid, o.exec);
+            let path = absolute(program, &o.path);
+            ops.push((o.timestamp, program, Op::Write(path)));
+        }
+        for r in &p.renamed {
+            let program = tree.program(p.pid, r.exec);
+            let from = absolute(program, &r.from);
+            let to = absolute(program, &r.to);
+            ops.push((r.timestamp, program, Op::Rename(from, to)));
+        }
+    }
+    ops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut files = HashMap::new();
+    for (timestamp, program, op) in ops {
+        let generator = program.map_or_else(|| "?".to_owned(), name);
+        match op {
+            Op::Write(path) => {
+                files.insert(
+                    path,
+                    Writer {
+                        generator,
+                        timestamp,
+                    },
+                );
+            }
+            Op::Rename(from, to) => {
+                let writer = files.remove(&from).unwrap_or(Writer {
+                    generator,
+                    timestamp,
+                });
+                files.insert(to, writer);
+            }
+        }
+    }
+    files
+}
+
+/// The inputs of the compile `e` and the `headers` it read that were
+/// generated before it ran.
+pub fn generated_inputs(
+    files: &HashMap<String, Writer>,
+    e: &Exec,
+    headers: &[String],
+) -> Vec<Generated> {
+    let inputs = driver::inputs(&e.args)
+        .into_iter()
+        .map(|i| absolute(Some(e), i.path))
+        .collect::<Vec<_>>();
+    let mut res = inputs
+        .iter()
+        .chain(headers)
+        .filter_map(|f| {
+            let w = files.get(f)?;
+            match (w.timestamp, e.timestamp) {
+                (Some(written), Some(started)) if written > started => None,
+                _ => Some(Generated {
+                    file: f.clone(),
+                    generator: w.generator.clone(),
+                }),
+            }
+        })
+        .collect::<Vec<_>>();
+    res.sort();
+    res.dedup();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+
+    fn exec(path: &str, args: &[&str], timestamp: f64) -> Event {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        e.timestamp = Some(timestamp);
+        Event::Exec(e)
+    }
+
+    fn write(path: &str, timestamp: f64) -> Event {
+        Event::Open {
+            path: path.to_owned(),
+            write: true,
+            timestamp: Some(timestamp),
+        }
+    }
+
+    #[test]
+    fn test_generated() {
+        let tree = ProcessTree::from(vec![
+            (
+                1,
+                vec![
+                    exec("/bin/sh", &["-c", "protoc > msg.pb.h"], 0.0),
+                    Event::Fork {
+                        child: 2,
+                        timestamp: Some(1.0),
+                    },
+                    Event::Fork {
+                        child: 3,
+                        timestamp: Some(1.0),
+                    },
+                ],
+            ),
+            // a shell redirection, before exec
+            (
+                2,
+                vec![write("msg.pb.h", 1.1), exec("/usr/bin/protoc", &[], 1.2)],
+            ),
+            (
+                3,
+                vec![
+                    exec("/usr/bin/bison", &["parse.y"], 1.0),
+                    write("parse.tmp", 1.1),
+                    Event::Rename {
+                        from: "parse.tmp".to_owned(),
+                        to: "parse.c".to_owned(),
+                        timestamp: Some(1.3),
+                    },
+                ],
+            ),
+            (4, vec![exec("/usr/bin/cc", &["-c", "parse.c"], 2.0)]),
+        ]);
+        let files = generated_files(&tree);
+        assert_eq!(files["/build/msg.pb.h"].generator, "protoc");
+        assert_eq!(files["/build/parse.c"].generator, "bison");
+        assert!(!files.contains_key("/build/parse.tmp"));
+
+        let cc = &tree.get(4).unwrap().execs[0];
+        let headers = vec![
+            "/build/msg.pb.h".to_owned(),
+            "/usr/include/stdio.h".to_owned(),
+        ];
+        assert_eq!(
+            generated_inputs(&files, cc, &headers),
+            vec![
+                Generated {
+                    file: "/build/msg.pb.h".to_owned(),
+                    generator: "protoc".to_owned(),
+                },
+                Generated {
+                    file: "/build/parse.c".to_owned(),
+                    generator: "bison".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+
+use crate::error::{write_json, Result};
+use crate::tools::ar::ArchiveIndex;
+use crate::tools::driver::{self, Language};
+use crate::tools::ld::{LibDirsCache, LinkCmd, LinkKind};
+use crate::tools::paths;
+use crate::tools::{CompilerAction, ToolKind};
+use crate::Exec;
+
+/// The kind of file a node of the build graph stands for.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    Source,
+    Object,
+    Archive,
+    SharedLibrary,
+    Executable,
+}
+
+impl ArtifactKind {
+    /// Guesses the kind of an input file from its name.
+    fn of_input(path: &str) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("");
+        if Language::from_path(path).is_some() {
+            ArtifactKind::Source
+        } else if name.ends_with(".a") {
+            ArtifactKind::Archive
+        } else if name.ends_with(".so") || name.contains(".so.") || name.ends_with(".dylib") {
+            ArtifactKind::SharedLibrary
+        } else {
+            ArtifactKind::Object
+        }
+    }
+
+    /// Returns true for artifacts that are loaded at runtime rather than
+    /// copied into the artifacts linked against them.
+    fn is_binary(self) -> bool {
+        self == ArtifactKind::SharedLibrary || self == ArtifactKind::Executable
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Node {
+    /// The absolute path of the artifact.
+    pub path: String,
+    pub kind: ArtifactKind,
+}
+
+/// Files read and written by the build, from sources through objects and
+/// archives to shared libraries and executables. An edge `(a, b)` means
+/// `a` is an input of the step producing `b`.
+#[derive(Serialize, Debug, Default)]
+pub struct BuildGraph {
+    pub nodes: Vec<Node>,
+    pub edges: BTreeSet<(usize, usize)>,
+    #[serde(skip)]
+    ids: HashMap<String, usize>,
+    /// The targets of the edges from each node, sorted.
+    #[serde(skip)]
+    forward: HashMap<usize, Vec<usize>>,
+    /// The sources of the edges to each node, sorted.
+    #[serde(skip)]
+    backward: HashMap<usize, Vec<usize>>,
+}
+
+fn absolute(dir: &str, path: &str) -> String {
+    paths::clean(&Path::new(dir).join(path))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn pwd(e: &Exec) -> Option<&str> {
+    e.env
+        .iter()
+        .find(|(k, _v)| k == "PWD")
+        .map(|(_k, v)| v.as_str())
+}
+
+impl BuildGraph {
+    /// Builds the graph from the compile, archive and link steps among `v`.
+    pub fn from(v: &[(Exec, ToolKind)]) -> Self {
+        let mut g = BuildGraph::default();
+        for (e, t) in v {
+            match t {
+                ToolKind::CCompiler(CompilerAction::Compile)
+                | ToolKind::CXXCompiler(CompilerAction::Compile) => g.add_compile(e),
+                ToolKind::Assembler => g.add_assemble(e),
+                _ => {}
+            }
+        }
+        for archive in ArchiveIndex::from(v).archives() {
+            let to = g.node(&archive.archive, ArtifactKind::Archive);
+            for m in &archive.members {
+                let from = g.node(m, ArtifactKind::of_input(m));
+                g.add_edge(from, to);
+            }
+        }
+        let mut lib_dirs = LibDirsCache::default();
+        for (e, t) in v {
+            if let Some(cmd) = LinkCmd::try_from(e, t, &mut lib_dirs) {
+                g.add_link(&cmd);
+            }
+        }
+        g
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        if self.edges.insert((from, to)) {
+            let insert_sorted = |v: &mut Vec<usize>, id| {
+                let i = v.binary_search(&id).unwrap_or_else(|i| i);
+                v.insert(i, id);
+            };
+            insert_sorted(self.forward.entry(from).or_default(), to);
+            insert_sorted(self.backward.entry(to).or_default(), from);
+        }
+    }
+
+    /// Returns the id of the node for `path`, adding it if necessary.
+    /// Outputs of build steps override the kind guessed for inputs.
+    fn node(&mut self, path: &str, kind: ArtifactKind) -> usize {
+        if let Some(&id) = self.ids.get(path) {
+            if kind != ArtifactKind::of_input(path) {
+                self.nodes[id].kind = kind;
+            }
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            path: path.to_owned(),
+            kind,
+        });
+        self.ids.insert(path.to_owned(), id);
+        id
+    }
+
+    fn add_compile(&mut self, e: &Exec) {
+        let dir = match pwd(e) {
+            Some(dir) => dir,
+            None => return,
+        };
+        let sources = driver::inputs(&e.args)
+            .into_iter()
+            .filter(|i| i.language.as_ref().is_some_and(|l| l.is_source()))
+            .map(|i| i.path)
+            .collect::<Vec<_>>();
+        let output = driver::output(&e.args);
+        for src in &sources {
+            // without -o, each source is compiled to its stem with .o in
+            // the working directory
+            let obj = match output {
+                Some(o) if sources.len() == 1 => o.to_owned(),
+                _ => Path::new(src)
+                    .with_extension("o")
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let from = self.node(&absolute(dir, src), ArtifactKind::Source);
+            let to = self.node(&absolute(dir, &obj), ArtifactKind::Object);
+            self.add_edge(from, to);
+        }
+    }
+
+    fn add_assemble(&mut self, e: &Exec) {
+        let dir = match pwd(e) {
+            Some(dir) => dir,
+            None => return,
+        };
+        let mut output = "a.out";
+        let mut sources = vec![];
+        let mut args = e.args.iter().skip(1);
+        while let Some(a) = args.next() {
+            match a.as_str() {
+                "-o" => output = args.next().map_or(output, |o| o.as_str()),
+                "-I" | "--defsym" | "--MD" => {
+                    args.next();
+                }
+                a if driver::is_input(a) && a != "-" => sources.push(a),
+                _ => {}
+            }
+        }
+        let to = self.node(&absolute(dir, output), ArtifactKind::Object);
+        for src in sources {
+            let from = self.node(&absolute(dir, src), ArtifactKind::Source);
+            self.add_edge(from, to);
+        }
+    }
+
+    fn add_link(&mut self, cmd: &LinkCmd) {
+        let kind = match cmd.kind {
+            LinkKind::Executable => ArtifactKind::Executable,
+            LinkKind::Shared => ArtifactKind::SharedLibrary,
+            LinkKind::Relocatable => ArtifactKind::Object,
+        };
+        let to = self.node(&absolute(&cmd.directory, &cmd.output), kind);
+        let inputs = cmd
+            .sources
+            .iter()
+            .chain(&cmd.objects)
+            .chain(&cmd.archives)
+            .chain(&cmd.shared_libraries)
+            .map(|i| absolute(&cmd.directory, i))
+            .chain(cmd.libraries.iter().filter_map(|l| l.path.clone()))
+            .collect::<Vec<_>>();
+        for i in inputs {
+            let from = self.node(&i, ArtifactKind::of_input(&i));
+            self.add_edge(from, to);
+        }
+    }
+
+    /// Finds the nodes for a path given on the command line: an exact
+    /// match after making it absolute, or else any path ending with it.
+    pub fn find(&self, path: &str) -> Vec<usize> {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let abs = paths::clean(&cwd.join(path));
+        if let Some(&id) = self.ids.get(abs.to_string_lossy().as_ref()) {
+            return vec![id];
+        }
+        let suffix = format!("/{}", path.trim_start_matches("./"));
+        let mut ids = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.path.ends_with(&suffix))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// Walks the graph from `start` in the given direction, not walking past
+    /// shared libraries and executables other than `start`: their contents
+    /// aren't part of the artifacts that link against them.
+    fn reachable(&self, start: usize, forward: bool) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut queue = VecDeque::new();
+        let mut res = vec![];
+        seen[start] = true;
+        queue.push_back(start);
+        while let Some(id) = queue.pop_front() {
+            if id != start {
+                res.push(id);
+                if self.nodes[id].kind.is_binary() {
+                    continue;
+                }
+            }
+            let next = if forward {
+                self.successors(id)
+            } else {
+                self.predecessors(id)
+            };
+            for &next in next {
+                if !seen[next] {
+                    seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        res
+    }
+
+    /// The shared libraries and executables that `source` ends up in.
+    pub fn binaries_containing(&self, source: usize) -> Vec<&Node> {
+        let mut res = self
+            .reachable(source, true)
+            .into_iter()
+            .map(|id| &self.nodes[id])
+            .filter(|n| n.kind.is_binary())
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.path.cmp(&b.path));
+        res
+    }
+
+    /// The final products of the build that contain `source`: shared
+    /// libraries, executables and static archives that aren't linked into
+    /// anything else.
+    pub fn products_containing(&self, source: usize) -> Vec<&Node> {
+        let mut res = self
+            .reachable(source, true)
+            .into_iter()
+            .filter(|&id| {
+                let kind = self.nodes[id].kind;
+                kind.is_binary()
+                    || (kind == ArtifactKind::Archive && self.successors(id).is_empty())
+            })
+            .map(|id| &self.nodes[id])
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.path.cmp(&b.path));
+        res
+    }
+
+    /// The artifacts produced by steps that `id` is a direct input of.
+    pub fn successors(&self, id: usize) -> &[usize] {
+        self.forward.get(&id).map_or(&[], |v| v.as_slice())
+    }
+
+    /// The direct inputs of the step producing `id`.
+    pub fn predecessors(&self, id: usize) -> &[usize] {
+        self.backward.get(&id).map_or(&[], |v| v.as_slice())
+    }
+
+    /// The sources compiled into `artifact`, excluding those of the shared
+    /// libraries it links against.
+    pub fn sources_of(&self, artifact: usize) -> Vec<&Node> {
+        let mut res = self
+            .reachable(artifact, false)
+            .into_iter()
+            .map(|id| &self.nodes[id])
+            .filter(|n| n.kind == ArtifactKind::Source)
+            .collect::<Vec<_>>();
+        res.sort_by(|a, b| a.path.cmp(&b.path));
+        res
+    }
+}
+
+pub fn write_build_graph(g: &BuildGraph) -> Result<()> {
+    write_json("build_graph.json", g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec(path: &str, args: &[&str]) -> (Exec, ToolKind) {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        let t = ToolKind::from(&e);
+        (e, t)
+    }
+
+    fn paths(nodes: Vec<&Node>) -> Vec<&str> {
+        nodes.into_iter().map(|n| n.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_build_graph() {
+        let execs = vec![
+            exec("/usr/bin/cc", &["-c", "src/foo.c", "-o", "foo.o"]),
+            exec("/usr/bin/cc", &["-c", "src/bar.c", "src/baz.c"]),
+            exec("/usr/bin/ar", &["rcs", "libfb.a", "foo.o", "bar.o"]),
+            exec("/usr/bin/cc", &["-shared", "-o", "libbaz.so", "baz.o"]),
+            exec(
+                "/usr/bin/cc",
+                &["-o", "app", "src/main.c", "libfb.a", "libbaz.so"],
+            ),
+        ];
+        let g = BuildGraph::from(&execs);
+
+        let foo = g.find("/build/src/foo.c");
+        assert_eq!(foo.len(), 1);
+        assert_eq!(paths(g.binaries_containing(foo[0])), vec!["/build/app"]);
+
+        let baz = g.find("baz.c");
+        assert_eq!(
+            paths(g.binaries_containing(baz[0])),
+            vec!["/build/libbaz.so"]
+        );
+
+        let app = g.find("/build/app");
+        assert_eq!(
+            paths(g.sources_of(app[0])),
+            vec!["/build/src/bar.c", "/build/src/foo.c", "/build/src/main.c"]
+        );
+        let libbaz = g.find("libbaz.so");
+        assert_eq!(g.nodes[libbaz[0]].kind, ArtifactKind::SharedLibrary);
+        assert_eq!(paths(g.sources_of(libbaz[0])), vec!["/build/src/baz.c"]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Headers read by compilations, from the files opened by compiler frontends.
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::process::{Process, ProcessTree};
+use crate::tools::{driver, paths};
+use crate::Exec;
+
+/// Returns true if `e` runs a compiler frontend: `cc1` and its siblings,
+/// `clang -cc1`, or a clang driver, which runs the frontend in-process.
+/// Other clang tools, such as `clang-tidy`, are not frontends.
+fn is_frontend(e: &Exec) -> bool {
+    lazy_static! {
+        static ref FRONTEND: Regex =
+            Regex::new(r"^(cc1(plus|obj|objplus)?|([^-]*-)*clang(\+\+)?(-\d+(\.\d+){0,2})?)$")
+                .unwrap();
+    }
+    let name = Path::new(&e.path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or("");
+    FRONTEND.is_match(name) || e.args.get(1).map(|a| a.as_str()) == Some("-cc1")
+}
+
+/// Returns false for files frontends read that are never included, such as
+/// the shared libraries they are linked against, locale data, and the
+/// programs and objects the clang driver looks up.
+fn may_be_header(path: &Path) -> bool {
+    const SYSTEM_DIRS: &[&str] = &[
+        "/bin",
+        "/dev",
+        "/etc",
+        "/proc",
+        "/sys",
+        "/usr/bin",
+        "/usr/lib/locale",
+        "/usr/share/locale",
+    ];
+    let name = path.file_name().and_then(|f| f.to_str()).unwrap_or("");
+    !SYSTEM_DIRS.iter().any(|d| path.starts_with(d))
+        && !name.ends_with(".so")
+        && !name.ends_with(".o")
+        && !name.ends_with(".a")
+        && !name.contains(".so.")
+}
+
+fn pwd(e: &Exec) -> &str {
+    e.env
+        .iter()
+        .find(|(k, _v)| k == "PWD")
+        .map_or("/", |(_k, v)| v.as_str())
+}
+
+/// The files read by the frontends run by the compile `e` and the child
+/// processes it forked, other than the sources on its command line. Paths
+/// are made absolute and sorted.
+pub fn headers_read(tree: &ProcessTree, e: &Exec) -> Vec<String> {
+    let sources = driver::inputs(&e.args)
+        .into_iter()
+        .map(|i| paths::clean(&Path::new(pwd(e)).join(i.path)))
+        .collect::<BTreeSet<_>>();
+    let p = match tree.get(e.pid) {
+        Some(p) => p,
+        None => return vec![],
+    };
+    // the process may run other programs before and after the compile,
+    // such as a shell running several compiles, the last one in place
+    let i = match p.execs.iter().position(|x| x == e) {
+        Some(i) => i,
+        None => return vec![],
+    };
+    let end = p.execs.get(i + 1).and_then(|next| next.timestamp);
+    let forked_by_e = |child: &Process| match child.start {
+        Some(t) => e.timestamp.is_none_or(|start| t >= start) && end.is_none_or(|end| t < end),
+        None => true,
+    };
+    let opened = p
+        .opened
+        .iter()
+        .filter(|o| o.exec == Some(i))
+        .map(|o| (p, o))
+        .chain(
+            p.children
+                .iter()
+                .filter_map(|&c| tree.get(c))
+                .filter(|c| forked_by_e(c))
+                .flat_map(|c| tree.subtree(c.pid))
+                .flat_map(|c| c.opened.iter().map(move |o| (c, o))),
+        );
+
+    let mut headers = BTreeSet::new();
+    for (p, o) in opened.filter(|(_, o)| !o.write) {
+        let frontend = match o.exec.map(|i| &p.execs[i]) {
+            Some(frontend) if is_frontend(frontend) => frontend,
+            _ => continue,
+        };
+        let path = paths::clean(&Path::new(pwd(frontend)).join(&o.path));
+        if may_be_header(&path) && !sources.contains(&path) {
+            headers.insert(path.to_string_lossy().into_owned());
+        }
+    }
+    headers.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+
+    fn exec(path: &str, args: &[&str]) -> Event {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        Event::Exec(e)
+    }
+
+    fn read(path: &str) -> Event {
+        Event::Open {
+            path: path.to_owned(),
+            write: false,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_is_frontend() {
+        let frontends = [
+            "/usr/lib/gcc/x86_64-linux-gnu/12/cc1",
+            "/usr/lib/gcc/x86_64-linux-gnu/12/cc1plus",
+            "/usr/bin/clang",
+            "/usr/bin/clang++-15",
+            "/usr/bin/aarch64-linux-gnu-clang",
+        ];
+        for path in &frontends {
+            assert!(is_frontend(&Exec::mock(path, &[])), "{}", path);
+        }
+        let others = [
+            "/usr/bin/clang-format",
+            "/usr/bin/clang-tidy",
+            "/usr/bin/clangd",
+            "/usr/bin/gcc",
+        ];
+        for path in &others {
+            assert!(!is_frontend(&Exec::mock(path, &[])), "{}", path);
+        }
+        assert!(is_frontend(&Exec::mock(
+            "/usr/bin/clang-15",
+            &["clang", "-cc1"]
+        )));
+    }
+
+    #[test]
+    fn test_headers_read() {
+        let tree = ProcessTree::from(vec![
+            (
+                1,
+                vec![
+                    exec("/usr/bin/gcc", &["-c", "src/a.c"]),
+                    read("/usr/lib/gcc/specs"),
+                    Event::Fork {
+                        child: 2,
+                        timestamp: None,
+                    },
+                ],
+            ),
+            (
+                2,
+                vec![
+                    exec("/usr/lib/gcc/cc1", &["-quiet", "src/a.c"]),
+                    read("/lib/x86_64-linux-gnu/libc.so.6"),
+                    read("src/a.c"),
+                    read("src/../include/a.h"),
+                    read("/usr/include/stdio.h"),
+                    read("/usr/include/c++/12/vector"),
+                    Event::Open {
+                        path: "/tmp/cc.s".to_owned(),
+                        write: true,
+                        timestamp: None,
+                    },
+                ],
+            ),
+        ]);
+        let gcc = &tree.get(1).unwrap().execs[0];
+        assert_eq!(
+            headers_read(&tree, gcc),
+            vec![
+                "/build/include/a.h",
+                "/usr/include/c++/12/vector",
+                "/usr/include/stdio.h"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_headers_read_in_place() {
+        // sh -c "gcc -c a.c && gcc -c b.c" runs the last gcc in place
+        let at = |event: Event, t: f64| match event {
+            Event::Exec(e) => Event::Exec(Exec {
+                timestamp: Some(t),
+                ..e
+            }),
+            Event::Open { path, write, .. } => Event::Open {
+                path,
+                write,
+                timestamp: Some(t),
+            },
+            event => event,
+        };
+        let fork = |child, t| Event::Fork {
+            child,
+            timestamp: Some(t),
+        };
+        let tree = ProcessTree::from(vec![
+            (
+                1,
+                vec![
+                    at(exec("/bin/sh", &["-c", "gcc -c a.c && gcc -c b.c"]), 0.0),
+                    fork(2, 1.0),
+                    at(exec("/usr/bin/gcc", &["-c", "b.c"]), 3.0),
+                    fork(4, 3.5),
+                ],
+            ),
+            (
+                2,
+                vec![at(exec("/usr/bin/gcc", &["-c", "a.c"]), 1.1), fork(3, 1.2)],
+            ),
+            (
+                3,
+                vec![
+                    at(exec("/usr/lib/gcc/cc1", &["a.c"]), 1.3),
+                    at(read("a.c"), 1.4),
+                    at(read("a.h"), 1.5),
+                ],
+            ),
+            (
+                4,
+                vec![
+                    at(exec("/usr/lib/gcc/cc1", &["b.c"]), 3.6),
+                    at(read("b.c"), 3.7),
+                    at(read("b.h"), 3.8),
+                ],
+            ),
+        ]);
+        let a = &tree.get(2).unwrap().execs[0];
+        assert_eq!(headers_read(&tree, a), vec!["/build/a.h"]);
+        let b = &tree.get(1).unwrap().execs[1];
+        assert_eq!(headers_read(&tree, b), vec!["/build/b.h"]);
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use regex::Regex;
+
+use crate::error::{write_json, Result};
+use crate::tools::driver;
+use crate::tools::paths;
+use crate::tools::{CompilerAction, ToolKind};
+use crate::Exec;
+
+/// The kind of artifact produced by a link step.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Executable,
+    /// `-shared`
+    Shared,
+    /// `-r`: a partial link producing another object file.
+    Relocatable,
+}
+
+/// A library named by `-l`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Library {
+    /// The name as given, e.g. `m` for `-lm` or `:libfoo.so.1` for `-l:libfoo.so.1`.
+    pub name: String,
+    /// The file the linker would pick, if it could be found.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// A single link step in `link_commands.json`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LinkCmd {
+    /// The working directory of the link. Relative paths are relative to it.
+    pub directory: String,
+    /// The executable, shared library or object file produced.
+    pub output: String,
+    pub kind: LinkKind,
+    /// Source files compiled by the driver as part of the link.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// Object files and other inputs passed on to the linker as-is.
+    pub objects: Vec<String>,
+    /// Static archives given by path.
+    pub archives: Vec<String>,
+    /// Shared libraries given by path.
+    pub shared_libraries: Vec<String>,
+    /// Libraries given with `-l`, in command line order.
+    pub libraries: Vec<Library>,
+    /// Library search directories given with `-L`.
+    pub search_paths: Vec<String>,
+    /// The remaining flags, e.g. `-Wl,--as-needed` or `-pthread`.
+    pub flags: Vec<String>,
+    /// The link command executed as a list of strings.
+    pub arguments: Vec<String>,
+}
+
+/// Parses the default library directories from the output of
+/// `cc -print-search-dirs` or, for `ld` itself, `ld --verbose`.
+fn parse_lib_dirs(output: &str) -> Vec<String> {
+    lazy_static! {
+        static ref SEARCH_DIR: Regex = Regex::new(r#"SEARCH_DIR\("=?([^"]*)"\)"#).unwrap();
+    }
+    match output.lines().find_map(|l| l.strip_prefix("libraries: ")) {
+        Some(dirs) => dirs
+            .trim_start_matches('=')
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(|d| d.to_owned())
+            .collect(),
+        None => SEARCH_DIR
+            .captures_iter(output)
+            .map(|c| c[1].to_owned())
+            .collect(),
+    }
+}
+
+/// Asks `linker` for the directories it searches for `-l` libraries by
+/// default. Returns nothing if it can't be run anymore.
+fn query_lib_dirs(linker: &str, is_driver: bool) -> Vec<String> {
+    let arg = if is_driver {
+        "-print-search-dirs"
+    } else {
+        "--verbose"
+    };
+    Command::new(linker)
+        .arg(arg)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map_or(vec![], |o| {
+            parse_lib_dirs(&String::from_utf8_lossy(&o.stdout))
+        })
+}
+
+/// Queries the default library directories of each linker once.
+#[derive(Debug, Default)]
+pub struct LibDirsCache {
+    cache: HashMap<String, Vec<String>>,
+}
+
+impl LibDirsCache {
+    pub fn get(&mut self, linker: &str, is_driver: bool) -> &[String] {
+        self.cache
+            .entry(linker.to_owned())
+            .or_insert_with(|| query_lib_dirs(linker, is_driver))
+    }
+}
+
+fn is_shared_library(file: &str) -> bool {
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    name.ends_with(".so") || name.contains(".so.") || name.ends_with(".dylib")
+}
+
+/// Flags of `ld` itself that take a separate operand.
+fn ld_takes_operand(arg: &str) -> bool {
+    lazy_static! {
+        static ref OPERAND_FLAGS: HashSet<&'static str> = {
+            let mut s = HashSet::new();
+            for f in &[
+                "-o",
+                "-l",
+                "-L",
+                "-m",
+                "-T",
+                "-e",
+                "-h",
+                "-soname",
+                "-z",
+                "-rpath",
+                "-rpath-link",
+                "-dynamic-linker",
+                "--dynamic-linker",
+                "-plugin",
+                "-plugin-opt",
+                "-u",
+                "-y",
+                "-Y",
+                "-A",
+                "-b",
+                "-F",
+                "-f",
+                "-G",
+                "-R",
+                "-Map",
+                "--version-script",
+                "-assert",
+                "--hash-style",
+            ] {
+                s.insert(*f);
+            }
+            s
+        };
+    }
+    OPERAND_FLAGS.contains(arg)
+}
+
+impl LinkCmd {
+    /// Builds the link step for a compiler driver or `ld` invocation,
+    /// resolving `-l` libraries in the default directories of the linker
+    /// from `lib_dirs`. Returns `None` for executions that don't link.
+    pub fn try_from(e: &Exec, t: &ToolKind, lib_dirs: &mut LibDirsCache) -> Option<Self> {
+        let is_driver = match t {
+            ToolKind::CCompiler(CompilerAction::Link)
+            | ToolKind::CXXCompiler(CompilerAction::Link) => true,
+            ToolKind::Linker => false,
+            _ => return None,
+        };
+        let directory = e.env.iter().find(|(k, _v)| k == "PWD")?.1.clone();
+
+        let mut cmd = LinkCmd {
+            directory,
+            output: "a.out".to_owned(),
+            kind: LinkKind::Executable,
+            sources: vec![],
+            objects: vec![],
+            archives: vec![],
+            shared_libraries: vec![],
+            libraries: vec![],
+            search_paths: vec![],
+            flags: vec![],
+            arguments: e.args.clone(),
+        };
+        // `-l` libraries are resolved once all `-L` paths are known, with
+        // whether `-Bstatic` was in effect at their position.
+        let mut libs = vec![];
+        let mut link_static = false;
+        let mut lang = driver::LanguageSwitch::default();
+
+        let mut args = e.args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let arg = arg.as_str();
+            let operand = if (is_driver && driver::takes_operand(arg))
+                || (!is_driver && ld_takes_operand(arg))
+            {
+                args.next().map(|s| s.as_str())
+            } else {
+                None
+            };
+
+            // linker options that the driver passes through with -Wl,
+            let ld_opts: Vec<&str> = match arg.strip_prefix("-Wl,") {
+                Some(opts) if is_driver => opts.split(',').collect(),
+                _ => vec![arg],
+            };
+            for opt in &ld_opts {
+                match *opt {
+                    "-Bstatic" | "-dn" | "-non_shared" => link_static = true,
+                    "-Bdynamic" | "-dy" | "-call_shared" => link_static = false,
+                    "-static" => link_static = true,
+                    "-shared" | "-Bshareable" => cmd.kind = LinkKind::Shared,
+                    "-r" | "--relocatable" | "-relocatable" => cmd.kind = LinkKind::Relocatable,
+                    _ => {}
+                }
+            }
+
+            if is_driver && lang.update(arg, operand) {
+                cmd.flags.push(arg.to_owned());
+                cmd.flags.extend(operand.map(|o| o.to_owned()));
+            } else if arg == "-o" || arg == "-l" || arg == "-L" {
+                // a trailing flag missing its operand is ignored
+                let operand = match operand {
+                    Some(o) => o.to_owned(),
+                    None => continue,
+                };
+                match arg {
+                    "-o" => cmd.output = operand,
+                    "-l" => libs.push((operand, link_static)),
+                    _ => cmd.search_paths.push(operand),
+                }
+            } else if let Some(name) = arg.strip_prefix("-l") {
+                libs.push((name.to_owned(), link_static));
+            } else if let Some(dir) = arg.strip_prefix("-L") {
+                cmd.search_paths.push(dir.to_owned());
+            } else if arg.starts_with("--library=") || arg.starts_with("--library-path=") {
+                let (flag, value) = arg.split_at(arg.find('=').unwrap() + 1);
+                if flag == "--library=" {
+                    libs.push((value.to_owned(), link_static));
+                } else {
+                    cmd.search_paths.push(value.to_owned());
+                }
+            } else if arg.starts_with('-') && arg != "-" {
+                cmd.flags.push(arg.to_owned());
+                cmd.flags.extend(operand.map(|o| o.to_owned()));
+            } else if is_driver && lang.language_of(arg).is_some() {
+                cmd.sources.push(arg.to_owned());
+            } else if arg.ends_with(".a") {
+                cmd.archives.push(arg.to_owned());
+            } else if is_shared_library(arg) {
+                cmd.shared_libraries.push(arg.to_owned());
+            } else {
+                cmd.objects.push(arg.to_owned());
+            }
+        }
+
+        let library_path = e
+            .env
+            .iter()
+            .find(|(k, _v)| k == "LIBRARY_PATH")
+            .map(|(_k, v)| v.as_str());
+        let dirs = cmd.search_dirs(
+            if is_driver { library_path } else { None },
+            lib_dirs.get(&e.path, is_driver),
+        );
+        cmd.libraries = libs
+            .into_iter()
+            .map(|(name, link_static)| {
+                let path = resolve_library(&name, &dirs, link_static)
+                    .map(|p| p.to_string_lossy().into_owned());
+                Library { name, path }
+            })
+            .collect();
+        Some(cmd)
+    }
+
+    /// The directories searched for `-l` libraries, in order: `-L` paths,
+    /// then `LIBRARY_PATH` (honored by the compiler driver only), then the
+    /// default directories of the linker.
+    fn search_dirs(&self, library_path: Option<&str>, default_dirs: &[String]) -> Vec<PathBuf> {
+        let dir = Path::new(&self.directory);
+        self.search_paths
+            .iter()
+            .map(|p| p.as_str())
+            .chain(library_path.into_iter().flat_map(|lp| lp.split(':')))
+            .filter(|p| !p.is_empty())
+            .chain(default_dirs.iter().map(|d| d.as_str()))
+            .map(|p| paths::clean(&dir.join(p)))
+            .collect()
+    }
+}
+
+/// Finds the file the linker would use for `-l<name>`: `lib<name>.so` or,
+/// when linking statically or if there is no shared library in a
+/// directory, `lib<name>.a`. `-l:<file>` searches for `<file>` verbatim.
+pub fn resolve_library(name: &str, dirs: &[PathBuf], link_static: bool) -> Option<PathBuf> {
+    let candidates = match name.strip_prefix(':') {
+        Some(file) => vec![file.to_owned()],
+        None if link_static => vec![format!("lib{}.a", name)],
+        None => vec![format!("lib{}.so", name), format!("lib{}.a", name)],
+    };
+    dirs.iter()
+        .flat_map(|d| candidates.iter().map(move |c| d.join(c)))
+        .find(|p| p.is_file())
+}
+
+/// Returns true if `a` and `b` produce the same output in the same directory.
+fn same_link(a: &LinkCmd, b: &LinkCmd) -> bool {
+    a.directory == b.directory && a.output == b.output
+}
+
+/// Collects the link steps among `v`. Compiler drivers run the linker
+/// themselves, so an `ld` invocation producing the same output in the same
+/// directory as a recorded driver link is not listed separately.
+pub fn link_cmds(v: &[(Exec, ToolKind)]) -> Vec<LinkCmd> {
+    let mut lib_dirs = LibDirsCache::default();
+    let (driver_links, ld_links): (Vec<_>, Vec<_>) = v
+        .iter()
+        .filter_map(|(e, t)| {
+            LinkCmd::try_from(e, t, &mut lib_dirs).map(|c| (c, t == &ToolKind::Linker))
+        })
+        .partition(|(_, is_ld)| !is_ld);
+    let mut cmds = driver_links.into_iter().map(|(c, _)| c).collect::<Vec<_>>();
+    for (ld, _) in ld_links {
+        if !cmds.iter().any(|c| same_link(c, &ld)) {
+            cmds.push(ld);
+        }
+    }
+    cmds.sort_by(|a, b| {
+        (&a.directory, &a.output, &a.arguments).cmp(&(&b.directory, &b.output, &b.arguments))
+    });
+    cmds.dedup();
+    cmds
+}
+
+pub fn write_link_commands(v: &[(Exec, ToolKind)]) -> Result<()> {
+    let cmds = link_cmds(v);
+    write_json("link_commands.json", &cmds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn link(path: &str, args: &[&str]) -> LinkCmd {
+        let mut e = Exec::mock(path, args);
+        e.env
+            .push(("PWD".to_owned(), "/nonexistent/build".to_owned()));
+        let mut lib_dirs = LibDirsCache::default();
+        lib_dirs.cache.insert(path.to_owned(), vec![]);
+        LinkCmd::try_from(&e, &ToolKind::from(&e), &mut lib_dirs).unwrap()
+    }
+
+    #[test]
+    fn test_driver_link() {
+        let cmd = link(
+            "/usr/bin/gcc",
+            &[
+                "-o",
+                "app",
+                "main.c",
+                "util.o",
+                "libfoo.a",
+                "libbar.so.1",
+                "-Lout",
+                "-lz",
+                "-Wl,-Bstatic",
+                "-l",
+                "baz",
+                "-Wl,--as-needed",
+                "-pthread",
+            ],
+        );
+        assert_eq!(cmd.output, "app");
+        assert_eq!(cmd.kind, LinkKind::Executable);
+        assert_eq!(cmd.sources, vec!["main.c"]);
+        assert_eq!(cmd.objects, vec!["util.o"]);
+        assert_eq!(cmd.archives, vec!["libfoo.a"]);
+        assert_eq!(cmd.shared_libraries, vec!["libbar.so.1"]);
+        assert_eq!(cmd.search_paths, vec!["out"]);
+        assert_eq!(
+            cmd.libraries
+                .iter()
+                .map(|l| l.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["z", "baz"]
+        );
+        assert_eq!(
+            cmd.flags,
+            vec!["-Wl,-Bstatic", "-Wl,--as-needed", "-pthread"]
+        );
+    }
+
+    #[test]
+    fn test_ld_link() {
+        let cmd = link(
+            "/usr/bin/ld",
+            &[
+                "-shared",
+                "-soname",
+                "libx.so.1",
+                "-o",
+                "libx.so",
+                "a.o",
+                "-lc",
+            ],
+        );
+        assert_eq!(cmd.output, "libx.so");
+        assert_eq!(cmd.kind, LinkKind::Shared);
+        assert_eq!(cmd.objects, vec!["a.o"]);
+        assert_eq!(cmd.flags, vec!["-shared", "-soname", "libx.so.1"]);
+
+        let cmd = link("/usr/bin/ld", &["-r", "a.o", "b.o", "-o", "ab.o"]);
+        assert_eq!(cmd.kind, LinkKind::Relocatable);
+        assert_eq!(cmd.objects, vec!["a.o", "b.o"]);
+
+        let cmd = link("/usr/bin/ld", &["-o", "app", "a.o", "-L"]);
+        assert_eq!(cmd.output, "app");
+        assert!(cmd.search_paths.is_empty());
+        let cmd = link("/usr/bin/gcc", &["-o", "app", "a.o", "-l"]);
+        assert!(cmd.libraries.is_empty());
+    }
+
+    #[test]
+    fn test_parse_lib_dirs() {
+        assert_eq!(
+            parse_lib_dirs(
+                "install: /usr/lib/gcc/x86_64-linux-gnu/12/\n\
+                 programs: =/usr/libexec/gcc/x86_64-linux-gnu/12/\n\
+                 libraries: =/usr/lib/gcc/x86_64-linux-gnu/12/:/lib/x86_64-linux-gnu/:/usr/lib/\n"
+            ),
+            vec![
+                "/usr/lib/gcc/x86_64-linux-gnu/12/",
+                "/lib/x86_64-linux-gnu/",
+                "/usr/lib/"
+            ]
+        );
+        assert_eq!(
+            parse_lib_dirs(
+                r#"SEARCH_DIR("=/usr/local/lib64"); SEARCH_DIR("=/lib64"); SEARCH_DIR("/opt/lib");"#
+            ),
+            vec!["/usr/local/lib64", "/lib64", "/opt/lib"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_library() {
+        let dir = tempfile::tempdir().unwrap();
+        File::create(dir.path().join("libfoo.a")).unwrap();
+        File::create(dir.path().join("libfoo.so")).unwrap();
+        File::create(dir.path().join("libbar.a")).unwrap();
+        let dirs = vec![dir.path().to_owned()];
+        assert_eq!(
+            resolve_library("foo", &dirs, false),
+            Some(dir.path().join("libfoo.so"))
+        );
+        assert_eq!(
+            resolve_library("foo", &dirs, true),
+            Some(dir.path().join("libfoo.a"))
+        );
+        assert_eq!(
+            resolve_library("bar", &dirs, false),
+            Some(dir.path().join("libbar.a"))
+        );
+        assert_eq!(
+            resolve_library(":libfoo.a", &dirs, false),
+            Some(dir.path().join("libfoo.a"))
+        );
+        assert_eq!(resolve_library("baz", &dirs, false), None);
+    }
+}
@@ -2,33 +2,110 @@ use crate::Exec;
 use regex::Regex;
 use std::path::Path;
 
+pub mod ar;
+pub mod builtins;
 pub mod cc;
+pub mod ccmd;
+pub mod dot;
+pub mod driver;
+pub mod flags;
+pub mod generated;
+pub mod graph;
+pub mod includes;
+pub mod ld;
+pub mod modules;
+pub mod paths;
+pub mod probes;
+pub mod report;
+pub mod target;
+pub mod timeline;
+pub mod verify;
 
 #[derive(Debug, PartialEq)]
 pub enum CompilerAction {
+    /// `-E`, `-M` or `-MM`: run the preprocessor only.
+    Preprocess,
+    /// `-fsyntax-only`: parse and type check without producing output.
+    SyntaxOnly,
+    /// `-c`: compile or assemble to an object file.
     Compile,
+    /// `-S`: compile to assembly.
     EmitAsm,
+    /// No stop-phase flag: compile (if needed) and link the inputs.
     Link,
+    /// `-c` or no stop-phase flag with only header inputs, e.g.
+    /// `-x c-header foo.h -o foo.h.gch`: generate a precompiled header.
+    Precompile,
+    /// Anything that doesn't process inputs, e.g. `--version`, `-###` or
+    /// a `clang -cc1` frontend invocation.
     Other,
 }
 
 impl CompilerAction {
-    // args from compiler invocation
-    pub fn from(args: &Vec<String>) -> Self {
+    /// Classifies a compiler driver invocation following GCC/Clang
+    /// semantics: `-E`, `-M` and `-MM` override every other mode, otherwise
+    /// the last of `-c`, `-S` and `-fsyntax-only` wins. Linker flags only
+    /// matter when none of those are present. Compiling nothing but
+    /// headers generates a precompiled header.
+    pub fn from(args: &[String]) -> Self {
         lazy_static! {
-            static ref LINKING_ARG: Regex = Regex::new(r"^-(l|L|Wl,).+").unwrap();
+            static ref LINKING_ARG: Regex = Regex::new(r"^-(l|Wl,).+").unwrap();
         }
 
-        for a in args {
-            if LINKING_ARG.is_match(a) {
-                return CompilerAction::Link;
-            } else if a == "-S" {
-                return CompilerAction::EmitAsm;
-            } else if a == "-c" {
-                return CompilerAction::Compile;
+        // `clang -cc1` and `clang -cc1as` are internal frontend invocations
+        // spawned by the driver; the driver invocation is what we classify.
+        if let Some(a) = args.get(1) {
+            if a == "-cc1" || a == "-cc1as" {
+                return CompilerAction::Other;
             }
         }
-        CompilerAction::Other
+
+        let mut preprocess = false;
+        let mut dry_run = false;
+        let mut phase = None;
+        let mut has_input = false;
+        driver::for_each_arg(args, |a, operand| match a {
+            "-E" | "-M" | "-MM" => preprocess = true,
+            "-###" => dry_run = true,
+            "-c" => phase = Some(CompilerAction::Compile),
+            "-S" => phase = Some(CompilerAction::EmitAsm),
+            "-fsyntax-only" => phase = Some(CompilerAction::SyntaxOnly),
+            // `-l foo` names a library input just like `-lfoo`
+            "-l" if operand.is_some() => has_input = true,
+            _ => {
+                if driver::is_input(a) || LINKING_ARG.is_match(a) {
+                    has_input = true;
+                }
+            }
+        });
+
+        let action = if dry_run {
+            CompilerAction::Other
+        } else if preprocess {
+            CompilerAction::Preprocess
+        } else if let Some(phase) = phase {
+            phase
+        } else if has_input {
+            CompilerAction::Link
+        } else {
+            CompilerAction::Other
+        };
+
+        match action {
+            CompilerAction::Compile | CompilerAction::Link => {
+                let inputs = driver::inputs(args);
+                let only_headers = !inputs.is_empty()
+                    && inputs
+                        .iter()
+                        .all(|i| i.language.as_ref().is_some_and(|l| l.is_header()));
+                if only_headers {
+                    CompilerAction::Precompile
+                } else {
+                    action
+                }
+            }
+            _ => action,
+        }
     }
 }
 
@@ -38,6 +115,7 @@ pub enum ToolKind {
     CXXCompiler(CompilerAction),
     CompilerWrapper,
     Linker,
+    Assembler,
     Archiver,
     Unknown,
 }
@@ -60,6 +138,10 @@ impl ToolKind {
 
             // Linker and wrapper patterns used in intercept-build
             static ref LD: Regex = Regex::new(r"^ld(\.(bfd|gold))?$").unwrap();
+            // GNU assembler, possibly prefixed with a target triple
+            static ref AS: Regex = Regex::new(r"^([^-]*-)*as$").unwrap();
+            // archivers, including llvm-ar, gcc-ar and ranlib
+            static ref AR: Regex = Regex::new(r"^([^-]*-)*(ar|ranlib)(-\d+(\.\d+){0,2})?$").unwrap();
             static ref CC_WRAPPER: Regex = Regex::new(r"^(distcc|ccache)$").unwrap();
             static ref CC_MPI_WRAPPER: Regex = Regex::new(r"^mpi(cc|cxx|CC|c\+\+)$").unwrap();
         }
@@ -79,7 +161,10 @@ impl ToolKind {
             return ToolKind::CXXCompiler(action);
         } else if LD.is_match(file) {
             return ToolKind::Linker;
-        } else if file == "ar" {
+        } else if AS.is_match(file) && !file.ends_with("llvm-as") {
+            // llvm-as assembles LLVM IR rather than machine code
+            return ToolKind::Assembler;
+        } else if AR.is_match(file) {
             return ToolKind::Archiver;
         } else if CC_WRAPPER.is_match(file) || CC_MPI_WRAPPER.is_match(file) {
             return ToolKind::CompilerWrapper;
@@ -94,16 +179,22 @@ mod tests {
     use super::*;
 
     impl Exec {
+        /// Mocks an exec of `path`; like `execve`, `args` is preceded by `argv[0]`.
         pub fn mock(path: &str, args: &[&str]) -> Self {
             let path = path.to_owned();
             let env = vec![];
-            let args = args.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+            let args = std::iter::once(path.as_str())
+                .chain(args.iter().cloned())
+                .map(|s| s.to_string())
+                .collect::<Vec<String>>();
             let retcode = 0;
             Exec {
+                pid: 0,
                 path,
                 args,
                 env,
                 retcode,
+                timestamp: None,
             }
         }
     }
@@ -135,5 +226,91 @@ mod tests {
                 ToolKind::CXXCompiler(CompilerAction::Compile)
             );
         }
+
+        let ar_paths = &[
+            "/usr/bin/ar",
+            "/usr/bin/llvm-ar",
+            "/usr/bin/llvm-ar-14",
+            "/usr/bin/gcc-ar",
+            "/usr/bin/x86_64-linux-gnu-gcc-ar-9",
+            "/usr/bin/ranlib",
+        ];
+        for ar in ar_paths {
+            assert_eq!(
+                ToolKind::from(&Exec::mock(ar, &["rcs", "libfoo.a", "foo.o"])),
+                ToolKind::Archiver
+            );
+        }
+
+        for asm in &["/usr/bin/as", "/opt/gcc/bin/arm-none-eabi-as"] {
+            assert_eq!(
+                ToolKind::from(&Exec::mock(asm, &["-o", "foo.o", "foo.s"])),
+                ToolKind::Assembler
+            );
+        }
+        assert_eq!(
+            ToolKind::from(&Exec::mock("/usr/bin/llvm-as", &["-o", "foo.bc", "foo.ll"])),
+            ToolKind::Unknown
+        );
+    }
+
+    #[test]
+    fn test_compiler_action_from() {
+        use CompilerAction::*;
+        let table: &[(&[&str], CompilerAction)] = &[
+            (&["-c", "foo.c"], Compile),
+            (&["-c", "foo.c", "-o", "foo.o"], Compile),
+            (&["-S", "foo.c"], EmitAsm),
+            (&["foo.c", "-o", "foo"], Link),
+            (&["foo.o", "bar.o", "-o", "foo"], Link),
+            (&["-lfoo"], Link),
+            (&["-l", "foo"], Link),
+            (&["-Wl,--as-needed", "foo.o"], Link),
+            // linker flags are ignored when a stop-phase flag is present
+            (&["-L/opt/lib", "-c", "foo.c"], Compile),
+            (&["-lm", "-S", "foo.c"], EmitAsm),
+            (&["-Wl,-z,defs", "-c", "foo.c"], Compile),
+            // the last of -c, -S and -fsyntax-only wins
+            (&["-c", "foo.c", "-S"], EmitAsm),
+            (&["-S", "foo.c", "-c"], Compile),
+            (&["-c", "-fsyntax-only", "foo.c"], SyntaxOnly),
+            (&["-fsyntax-only", "foo.c"], SyntaxOnly),
+            // -E, -M and -MM override everything else
+            (&["-E", "foo.c"], Preprocess),
+            (&["-c", "foo.c", "-E"], Preprocess),
+            (&["-S", "-E", "foo.c"], Preprocess),
+            (&["-M", "foo.c"], Preprocess),
+            (&["-MM", "-c", "foo.c"], Preprocess),
+            // dependency generation alongside a compile is still a compile
+            (&["-MD", "-MF", "foo.d", "-c", "foo.c"], Compile),
+            (&["-MMD", "-c", "foo.c"], Compile),
+            // operands of flags are not inputs
+            (&["-o", "foo"], Other),
+            (&["-I", "include", "-D", "FOO"], Other),
+            (&["-x", "c", "-c", "-"], Compile),
+            (&["-x", "c", "-c", "generated.inc"], Compile),
+            // compiling only headers generates a precompiled header
+            (&["-x", "c-header", "foo.h", "-o", "foo.h.gch"], Precompile),
+            (&["-xc++-header", "-c", "foo.hpp"], Precompile),
+            (&["-c", "foo.h"], Precompile),
+            (&["-x", "c++-header", "-c", "-"], Precompile),
+            (
+                &["-x", "c-header", "foo.h", "-x", "none", "-c", "foo.c"],
+                Compile,
+            ),
+            (&["-E", "foo.h"], Preprocess),
+            (&["-###", "-c", "foo.c"], Other),
+            (&["-cc1", "-emit-obj", "foo.c"], Other),
+            (&["-cc1as", "foo.s"], Other),
+            (&["--version"], Other),
+            (&["-v"], Other),
+            (&["-dumpmachine"], Other),
+            (&[], Other),
+        ];
+        for (args, expected) in table {
+            let mut argv = vec!["gcc".to_string()];
+            argv.extend(args.iter().map(|s| s.to_string()));
+            assert_eq!(&CompilerAction::from(&argv), expected, "args: {:?}", args);
+        }
     }
 }
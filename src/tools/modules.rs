@@ -0,0 +1,172 @@
+//! Classification of translation units by the products they end up in.
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::{write_json, Result};
+use crate::tools::graph::{ArtifactKind, BuildGraph};
+
+/// A translation unit and the products of the build it is part of.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Module {
+    /// The absolute path of the source file.
+    pub file: String,
+    /// The objects it was compiled to.
+    pub objects: Vec<String>,
+    /// The executables it is linked into.
+    pub executables: Vec<String>,
+    /// The shared libraries, and static archives that aren't linked into
+    /// anything else, it is part of.
+    pub libraries: Vec<String>,
+    /// Whether it defines `main`, i.e. is the entry point of a program, or
+    /// None if that couldn't be determined.
+    pub defines_main: Option<bool>,
+}
+
+/// Returns whether the object file at `path` defines a global `main`, or
+/// None if its symbol table couldn't be read.
+fn object_defines_main(path: &Path) -> Option<bool> {
+    let output = Command::new("nm")
+        .args(["-P", "-g", "--defined-only"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let symbols = String::from_utf8_lossy(&output.stdout);
+    Some(symbols.lines().any(is_main_symbol))
+}
+
+/// Matches a line of `nm -P` output defining `main` in a text section.
+/// Mach-O prefixes C symbols with an underscore.
+fn is_main_symbol(line: &str) -> bool {
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next()) {
+        (Some(name), Some(kind)) => (name == "main" || name == "_main") && kind == "T",
+        _ => false,
+    }
+}
+
+/// Finds the modules of `g`. Whether a module defines `main` is read from
+/// the symbol tables of its objects if they still exist; otherwise the
+/// only source or object given directly to an executable's link step is
+/// taken to be its entry point, and sources that aren't linked into any
+/// executable are taken not to be one.
+pub fn modules(g: &BuildGraph) -> Vec<Module> {
+    let linked_alone = |id: usize| {
+        g.successors(id).iter().any(|&exe| {
+            g.nodes[exe].kind == ArtifactKind::Executable
+                && g.predecessors(exe)
+                    .iter()
+                    .filter(|&&i| {
+                        let kind = g.nodes[i].kind;
+                        kind == ArtifactKind::Source || kind == ArtifactKind::Object
+                    })
+                    .count()
+                    == 1
+        })
+    };
+
+    let mut res = vec![];
+    for (id, node) in g.nodes.iter().enumerate() {
+        if node.kind != ArtifactKind::Source {
+            continue;
+        }
+        let objects = g
+            .successors(id)
+            .iter()
+            .copied()
+            .filter(|&o| g.nodes[o].kind == ArtifactKind::Object)
+            .collect::<Vec<_>>();
+        let mut executables = vec![];
+        let mut libraries = vec![];
+        for product in g.products_containing(id) {
+            match product.kind {
+                ArtifactKind::Executable => executables.push(product.path.clone()),
+                _ => libraries.push(product.path.clone()),
+            }
+        }
+        let scanned = objects
+            .iter()
+            .filter_map(|&o| object_defines_main(Path::new(&g.nodes[o].path)))
+            .collect::<Vec<_>>();
+        let defines_main = if !scanned.is_empty() {
+            Some(scanned.into_iter().any(|m| m))
+        } else if executables.is_empty() {
+            Some(false)
+        } else if linked_alone(id) || objects.iter().any(|&o| linked_alone(o)) {
+            Some(true)
+        } else {
+            None
+        };
+        res.push(Module {
+            file: node.path.clone(),
+            objects: objects.iter().map(|&o| g.nodes[o].path.clone()).collect(),
+            executables,
+            libraries,
+            defines_main,
+        });
+    }
+    res.sort_by(|a, b| a.file.cmp(&b.file));
+    res
+}
+
+pub fn write_modules(g: &BuildGraph) -> Result<()> {
+    write_json("modules.json", &modules(g))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolKind;
+    use crate::Exec;
+
+    fn exec(path: &str, args: &[&str]) -> (Exec, ToolKind) {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/nonexistent".to_owned()));
+        let t = ToolKind::from(&e);
+        (e, t)
+    }
+
+    #[test]
+    fn test_is_main_symbol() {
+        assert!(is_main_symbol("main T 0000000000001139 000000000000000b"));
+        assert!(is_main_symbol("_main T 0000000100003f90"));
+        assert!(!is_main_symbol("main U"));
+        assert!(!is_main_symbol("domain T 0000000000001139"));
+    }
+
+    #[test]
+    fn test_modules() {
+        let execs = vec![
+            exec("/usr/bin/cc", &["-c", "util.c", "app.c", "tool.c"]),
+            exec("/usr/bin/ar", &["rcs", "libutil.a", "util.o"]),
+            exec("/usr/bin/cc", &["-o", "app", "app.o", "libutil.a"]),
+            exec("/usr/bin/cc", &["-o", "tool", "tool.o", "util.o"]),
+        ];
+        let g = BuildGraph::from(&execs);
+        let m = modules(&g);
+        let files = m.iter().map(|m| m.file.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                "/nonexistent/app.c",
+                "/nonexistent/tool.c",
+                "/nonexistent/util.c"
+            ]
+        );
+        assert_eq!(m[0].defines_main, Some(true));
+        assert_eq!(m[0].executables, vec!["/nonexistent/app"]);
+        // tool.o is linked along with util.o, so its entry point is unknown
+        assert_eq!(m[1].defines_main, None);
+        assert_eq!(m[2].defines_main, None);
+        assert_eq!(
+            m[2].executables,
+            vec!["/nonexistent/app", "/nonexistent/tool"]
+        );
+        assert!(m[2].libraries.is_empty());
+        assert!(serde_json::to_string(&m[1])
+            .unwrap()
+            .ends_with(r#""defines_main":null}"#));
+    }
+}
@@ -0,0 +1,264 @@
+//! Normalization of the paths recorded in compilation databases.
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+
+use crate::tools::driver;
+
+/// Rewrites applied to paths in recorded commands so the same file is
+/// spelled the same way regardless of how the build referred to it.
+#[derive(Debug, Default, Clone)]
+pub struct PathOptions {
+    /// Make relative paths absolute by joining them to the directory.
+    pub absolute: bool,
+    /// Resolve symbolic links. Implies `absolute`; paths that don't exist
+    /// (anymore) are only cleaned lexically.
+    pub resolve_symlinks: bool,
+    /// Lexically remove `.` components and fold `..` into their parent.
+    pub clean: bool,
+    /// Replace this directory prefix of absolute paths with a placeholder.
+    pub root: Option<(PathBuf, String)>,
+}
+
+impl PathOptions {
+    /// Returns true if none of the rewrites are enabled.
+    pub fn is_identity(&self) -> bool {
+        !self.absolute && !self.resolve_symlinks && !self.clean && self.root.is_none()
+    }
+
+    /// Normalizes the working directory of a command.
+    pub fn normalize_dir(&self, dir: &str) -> String {
+        self.normalize(Path::new("/"), dir)
+    }
+
+    /// Normalizes `path`, which is relative to `dir` unless absolute.
+    /// `dir` itself must not have been normalized yet.
+    pub fn normalize(&self, dir: &Path, path: &str) -> String {
+        let mut p = PathBuf::from(path);
+        if self.absolute || self.resolve_symlinks {
+            p = dir.join(p);
+        }
+        if self.resolve_symlinks {
+            if let Ok(c) = p.canonicalize() {
+                p = c;
+            }
+        }
+        if self.clean || self.resolve_symlinks {
+            p = clean(&p);
+        }
+        if let Some((prefix, placeholder)) = &self.root {
+            if let Ok(rest) = p.strip_prefix(prefix) {
+                p = Path::new(placeholder).join(rest);
+            }
+        }
+        p.to_string_lossy().into_owned()
+    }
+
+    /// Normalizes the input files and the operands of path-valued flags,
+    /// such as `-I`, `-isystem`, `-include` and `-o`, of a compiler
+    /// command line. `args[0]` is left untouched.
+    pub fn normalize_args(&self, dir: &Path, args: &mut [String]) {
+        // flags whose operand is a path, attached or as the next argument.
+        // Longer prefixes come first so `-isystem` isn't read as `-i`.
+        const PATH_FLAGS: &[&str] = &[
+            "-iwithprefixbefore",
+            "-iwithprefix",
+            "-idirafter",
+            "-isysroot",
+            "-isystem",
+            "-imacros",
+            "-include-pch",
+            "-include",
+            "-iquote",
+            "-iprefix",
+            "--sysroot=",
+            "--sysroot",
+            "-MF",
+            "-I",
+            "-L",
+            "-o",
+        ];
+        let mut i = 1;
+        while i < args.len() {
+            let arg = args[i].clone();
+            // `-o` prefixes options like `-objcmt-*` whose value isn't a path
+            let is_path_flag = |f: &str| {
+                arg.starts_with(f)
+                    && (f != "-o" || arg == "-o" || driver::attached_output(&arg).is_some())
+            };
+            if let Some(flag) = PATH_FLAGS.iter().find(|f| is_path_flag(f)) {
+                if arg == *flag && !flag.ends_with('=') {
+                    if let Some(operand) = args.get_mut(i + 1) {
+                        *operand = self.normalize(dir, operand);
+                    }
+                    i += 1;
+                } else {
+                    let path = self.normalize(dir, &arg[flag.len()..]);
+                    args[i] = format!("{}{}", flag, path);
+                }
+            } else if driver::takes_operand(&arg) {
+                i += 1;
+            } else if driver::is_input(&arg) && arg != "-" {
+                args[i] = self.normalize(dir, &arg);
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Lexically normalizes `path`: drops `.` components and resolves `..`
+/// against the preceding component where there is one.
+pub fn clean(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => match res.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    res.pop();
+                }
+                // `/..` is `/`
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => res.push(".."),
+            },
+            c => res.push(c.as_os_str()),
+        }
+    }
+    if res.as_os_str().is_empty() {
+        res.push(".");
+    }
+    res
+}
+
+/// Compiles a shell-style glob into a regex matching whole absolute paths.
+/// `*` and `?` don't match `/`, `**/` matches any number of directories
+/// and `**` anything. Like in `.gitignore`, a relative glob containing a
+/// `/` is anchored at `root`, the top of the build, so `src/**` matches
+/// `$root/src/main.c` but not `$root/lib/src/x.c`, while one without a `/`
+/// matches a name at any depth below `root`.
+pub fn glob(pattern: &str, root: &Path) -> Regex {
+    let mut re = String::from("^");
+    let pattern = pattern.trim_start_matches("./");
+    if !pattern.starts_with('/') {
+        let root = root.to_string_lossy();
+        re.push_str(&regex::escape(root.trim_end_matches('/')));
+        re.push('/');
+        if !pattern.contains('/') {
+            re.push_str("(?:.*/)?");
+        }
+    }
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean() {
+        let cases = &[
+            ("a/./b/../c", "a/c"),
+            ("/a/../../b", "/b"),
+            ("../a/../../b", "../../b"),
+            ("./", "."),
+            ("a/..", "."),
+            ("/usr/include/", "/usr/include"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(clean(Path::new(input)), PathBuf::from(expected));
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        let root = Path::new("/home/src/project");
+        let g = glob("src/**", root);
+        assert!(g.is_match("/home/src/project/src/a.c"));
+        assert!(g.is_match("/home/src/project/src/lib/a.c"));
+        assert!(!g.is_match("/home/src/project/lib/a.c"));
+        assert!(!g.is_match("/home/src/project/lib/src/a.c"));
+        assert!(!g.is_match("/home/src/project/src2/a.c"));
+        assert!(glob("./src/**", root).is_match("/home/src/project/src/a.c"));
+
+        let g = glob("/build/**/test_*.c", root);
+        assert!(g.is_match("/build/test_a.c"));
+        assert!(g.is_match("/build/x/y/test_a.c"));
+        assert!(!g.is_match("/other/build/test_a.c"));
+        assert!(!g.is_match("/build/test_a.cc"));
+
+        let g = glob("conftest?.c", Path::new("/tmp/"));
+        assert!(g.is_match("/tmp/conftest1.c"));
+        assert!(g.is_match("/tmp/build/conftest1.c"));
+        assert!(!g.is_match("/tmp/conftest/1.c"));
+        assert!(!g.is_match("/var/tmp/conftest1.c"));
+    }
+
+    #[test]
+    fn test_normalize_args() {
+        let opts = PathOptions {
+            absolute: true,
+            clean: true,
+            root: Some((PathBuf::from("/build"), "$ROOT".to_owned())),
+            ..Default::default()
+        };
+        let dir = Path::new("/build/obj");
+        let mut args = [
+            "cc",
+            "-I../include",
+            "-isystem",
+            "/usr/include",
+            "-include",
+            "config.h",
+            "-DX=a/b",
+            "-x",
+            "c",
+            "-o",
+            "a.o",
+            "-objcmt-migrate-literals",
+            "-MFdeps/a.d",
+            "../src/a.c",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+        opts.normalize_args(dir, &mut args);
+        assert_eq!(
+            args,
+            vec![
+                "cc",
+                "-I$ROOT/include",
+                "-isystem",
+                "/usr/include",
+                "-include",
+                "$ROOT/obj/config.h",
+                "-DX=a/b",
+                "-x",
+                "c",
+                "-o",
+                "$ROOT/obj/a.o",
+                "-objcmt-migrate-literals",
+                "-MF$ROOT/obj/deps/a.d",
+                "$ROOT/src/a.c",
+            ]
+        );
+        assert_eq!(opts.normalize_dir("/build/obj/../obj"), "$ROOT/obj");
+    }
+}
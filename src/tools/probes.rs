@@ -0,0 +1,164 @@
+//! Detection of the compilations configure scripts and build system
+//! generators run to probe the compiler, such as autoconf's `conftest.c`,
+//! CMake's `try_compile` and Meson's sanity checks.
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::tools::{driver, paths};
+use crate::Exec;
+
+/// Controls which tool invocations are dropped as probes.
+#[derive(Debug, Default)]
+pub struct ProbeOptions {
+    /// Keep the invocations the heuristics consider probes.
+    pub keep: bool,
+    /// Globs of sources and working directories that are always probes,
+    /// made with `paths::glob`.
+    pub probes: Vec<Regex>,
+    /// Globs of sources and working directories that are never probes.
+    pub not_probes: Vec<Regex>,
+    /// The directory the build was started in. Sources below it are not
+    /// considered temporary even if it is itself in a temporary directory.
+    pub build_dir: Option<PathBuf>,
+}
+
+fn pwd(e: &Exec) -> &str {
+    e.env
+        .iter()
+        .find(|(k, _v)| k == "PWD")
+        .map_or("/", |(_k, v)| v.as_str())
+}
+
+/// Returns true if `path` is in the temporary directory of `e`.
+fn is_temporary(e: &Exec, path: &Path) -> bool {
+    let tmp_dir = e
+        .env
+        .iter()
+        .find(|(k, _v)| k == "TMPDIR")
+        .map(|(_k, v)| v.as_str());
+    tmp_dir
+        .into_iter()
+        .chain(["/tmp", "/var/tmp"].iter().cloned())
+        .any(|d| path.starts_with(d))
+}
+
+/// Returns true if `e` looks like a probe: it compiles or links a file
+/// named like those of autoconf, CMake or Meson checks, runs in one of
+/// their scratch directories, or compiles a source in a temporary
+/// directory. If the build itself runs in a temporary directory, only its
+/// sources that don't exist anymore are taken to be probes.
+fn looks_like_probe(e: &Exec, dir: &Path, files: &[(PathBuf, bool)], opts: &ProbeOptions) -> bool {
+    lazy_static! {
+        static ref PROBE_FILE: Regex = Regex::new(
+            r"^(conftest|CMakeC(XX)?CompilerId|CMake(C|CXX)CompilerABI|Check(SymbolExists|IncludeFiles?|FunctionExists|TypeSize)|testccompiler|testcppcompiler|sanitycheck\w*)\.\w+$"
+        )
+        .unwrap();
+        static ref PROBE_DIR: Regex = Regex::new(
+            r"/(CMakeFiles/(CMakeTmp|CMakeScratch|[^/]+/CompilerId\w*)|meson-private)(/|$)"
+        )
+        .unwrap();
+    }
+    let below_build_dir = |p: &Path| opts.build_dir.as_ref().is_some_and(|b| p.starts_with(b));
+    PROBE_DIR.is_match(&dir.to_string_lossy())
+        || files.iter().any(|(f, is_source)| {
+            let name = f.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            PROBE_FILE.is_match(name)
+                || PROBE_DIR.is_match(&f.to_string_lossy())
+                || (*is_source && is_temporary(e, f) && (!below_build_dir(f) || !f.exists()))
+        })
+}
+
+/// Returns true if `e` is a probe to leave out of the outputs.
+pub fn is_probe(e: &Exec, opts: &ProbeOptions) -> bool {
+    let dir = PathBuf::from(pwd(e));
+    let files = driver::inputs(&e.args)
+        .into_iter()
+        .map(|i| {
+            let is_source = i.language.is_some_and(|l| l.is_source());
+            (paths::clean(&dir.join(i.path)), is_source)
+        })
+        .collect::<Vec<_>>();
+    let matches = |globs: &[Regex]| {
+        globs.iter().any(|g| {
+            g.is_match(&dir.to_string_lossy())
+                || files.iter().any(|(f, _)| g.is_match(&f.to_string_lossy()))
+        })
+    };
+    if matches(&opts.not_probes) {
+        return false;
+    }
+    matches(&opts.probes) || (!opts.keep && looks_like_probe(e, &dir, &files, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    fn exec(dir: &Path, args: &[&str]) -> Exec {
+        let mut e = Exec::mock("/usr/bin/cc", args);
+        e.env
+            .push(("PWD".to_owned(), dir.to_string_lossy().into_owned()));
+        e
+    }
+
+    #[test]
+    fn test_is_probe() {
+        let build = tempdir().unwrap();
+        File::create(build.path().join("a.c")).unwrap();
+        File::create(build.path().join("conftest.c")).unwrap();
+        let scratch = build.path().join("CMakeFiles/CMakeScratch/TryCompile-1");
+        let opts = ProbeOptions {
+            build_dir: Some(build.path().to_owned()),
+            ..Default::default()
+        };
+
+        assert!(!is_probe(&exec(build.path(), &["-c", "a.c"]), &opts));
+        assert!(is_probe(
+            &exec(build.path(), &["-o", "conftest", "conftest.c"]),
+            &opts
+        ));
+        assert!(is_probe(&exec(build.path(), &["-c", "deleted.c"]), &opts));
+        // generated sources that were cleaned up afterwards are kept
+        assert!(!is_probe(
+            &exec(Path::new("/nonexistent/build"), &["-c", "gen.c"]),
+            &opts
+        ));
+        assert!(is_probe(&exec(&scratch, &["-c", "src.c"]), &opts));
+        assert!(is_probe(
+            &exec(Path::new("/"), &["-c", "/tmp/cc1.c"]),
+            &opts
+        ));
+
+        let keep = ProbeOptions {
+            keep: true,
+            probes: vec![paths::glob("a.c", build.path())],
+            ..Default::default()
+        };
+        assert!(!is_probe(&exec(build.path(), &["-c", "conftest.c"]), &keep));
+        assert!(is_probe(&exec(build.path(), &["-c", "a.c"]), &keep));
+
+        let anchored = ProbeOptions {
+            keep: true,
+            probes: vec![paths::glob("checks/**", build.path())],
+            ..Default::default()
+        };
+        let nested = build.path().join("lib/checks");
+        assert!(is_probe(
+            &exec(build.path(), &["-c", "checks/x.c"]),
+            &anchored
+        ));
+        assert!(!is_probe(&exec(&nested, &["-c", "x.c"]), &anchored));
+
+        let not_probes = ProbeOptions {
+            not_probes: vec![paths::glob("conftest.c", build.path())],
+            ..opts
+        };
+        assert!(!is_probe(
+            &exec(build.path(), &["-c", "conftest.c"]),
+            &not_probes
+        ));
+    }
+}
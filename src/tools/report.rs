@@ -0,0 +1,198 @@
+//! Build performance reports from the start and exit times of processes.
+use std::collections::BTreeMap;
+
+use crate::process::ProcessTree;
+use crate::tools::timeline::{tool_spans, Span};
+
+/// The `n` longest compiles, slowest first.
+pub fn slowest_compiles<'a, 'b>(spans: &'b [Span<'a>], n: usize) -> Vec<&'b Span<'a>> {
+    let mut compiles = spans
+        .iter()
+        .filter(|s| s.cat == "compile" && s.end.is_some())
+        .collect::<Vec<_>>();
+    compiles.sort_by(|a, b| b.duration().partial_cmp(&a.duration()).unwrap());
+    compiles.truncate(n);
+    compiles
+}
+
+/// The time spent compiling in each directory, largest first. Concurrent
+/// compiles each count in full, so this approximates CPU time.
+pub fn compile_time_per_directory<'a>(spans: &[Span<'a>]) -> Vec<(&'a str, f64)> {
+    let mut dirs = BTreeMap::new();
+    for s in spans.iter().filter(|s| s.cat == "compile") {
+        *dirs.entry(s.directory.unwrap_or("?")).or_insert(0.0) += s.duration();
+    }
+    let mut dirs = dirs.into_iter().collect::<Vec<_>>();
+    dirs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    dirs
+}
+
+/// The average number of tools running at once in each of `buckets`
+/// equal intervals between `start` and `end`.
+pub fn parallelism(spans: &[Span], start: f64, end: f64, buckets: usize) -> Vec<f64> {
+    let width = (end - start) / buckets as f64;
+    let mut res = vec![0.0; buckets];
+    if width <= 0.0 {
+        return res;
+    }
+    for s in spans {
+        let s_end = match s.end {
+            Some(end) => end,
+            None => continue,
+        };
+        for (i, busy) in res.iter_mut().enumerate() {
+            let (b_start, b_end) = (start + width * i as f64, start + width * (i + 1) as f64);
+            let overlap = s_end.min(b_end) - s.start.max(b_start);
+            if overlap > 0.0 {
+                *busy += overlap / width;
+            }
+        }
+    }
+    res
+}
+
+/// The chain of processes the build waited on: starting from the root,
+/// the child of each process that exited last.
+pub fn critical_path(tree: &ProcessTree) -> Vec<u32> {
+    let root = tree
+        .roots()
+        .into_iter()
+        .filter_map(|pid| tree.get(pid))
+        .min_by(|a, b| {
+            let (a, b) = (a.start.unwrap_or(0.0), b.start.unwrap_or(0.0));
+            a.partial_cmp(&b).unwrap()
+        });
+    let mut path = vec![];
+    let mut next = root;
+    while let Some(p) = next {
+        path.push(p.pid);
+        next = p
+            .children
+            .iter()
+            .filter_map(|c| tree.get(*c))
+            .filter(|c| c.end.is_some())
+            .max_by(|a, b| a.end.partial_cmp(&b.end).unwrap());
+    }
+    path
+}
+
+/// Prints the `top` slowest compiles, the compile time per directory, the
+/// parallelism over time and the critical path of the build.
+pub fn print_report(tree: &ProcessTree, top: usize) {
+    let spans = tool_spans(tree);
+    let (start, end) = match (tree.start(), tree.end()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => {
+            println!("no timing information was recorded");
+            return;
+        }
+    };
+
+    println!("slowest compiles:");
+    for s in slowest_compiles(&spans, top) {
+        println!(
+            "  {:>9.3}s  {}  ({})",
+            s.duration(),
+            s.name,
+            s.directory.unwrap_or("?")
+        );
+    }
+
+    println!("\ncompile time per directory:");
+    for (dir, time) in compile_time_per_directory(&spans).into_iter().take(top) {
+        println!("  {:>9.3}s  {}", time, dir);
+    }
+
+    let busy = spans.iter().map(|s| s.duration()).sum::<f64>();
+    println!(
+        "\nparallelism: {:.2} tools running on average over {:.3}s",
+        busy / (end - start).max(f64::EPSILON),
+        end - start
+    );
+    const BUCKETS: usize = 20;
+    let width = (end - start) / BUCKETS as f64;
+    for (i, p) in parallelism(&spans, start, end, BUCKETS)
+        .into_iter()
+        .enumerate()
+    {
+        let bar = "#".repeat((p * 4.0).round() as usize);
+        println!("  {:>9.3}s {:>6.2} {}", width * i as f64, p, bar);
+    }
+
+    println!("\ncritical path:");
+    for pid in critical_path(tree) {
+        let p = tree.get(pid).unwrap();
+        let p_start = p.start.unwrap_or(start);
+        println!(
+            "  {:>9.3}s +{:>9.3}s  {} ({})",
+            p_start - start,
+            p.end.map_or(0.0, |end| end - p_start),
+            tree.name(pid),
+            pid
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+    use crate::Exec;
+
+    fn exec(path: &str, args: &[&str], timestamp: f64) -> Event {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        e.timestamp = Some(timestamp);
+        Event::Exec(e)
+    }
+
+    fn fork(child: u32, timestamp: f64) -> Event {
+        Event::Fork {
+            child,
+            timestamp: Some(timestamp),
+        }
+    }
+
+    fn exit(timestamp: f64) -> Event {
+        Event::Exit {
+            retcode: Some(0),
+            timestamp: Some(timestamp),
+        }
+    }
+
+    fn tree() -> ProcessTree {
+        ProcessTree::from(vec![
+            (
+                1,
+                vec![
+                    exec("/usr/bin/make", &["-j2"], 0.0),
+                    fork(2, 0.0),
+                    fork(3, 0.0),
+                    fork(4, 2.0),
+                    exit(4.0),
+                ],
+            ),
+            (2, vec![exec("/usr/bin/cc", &["-c", "a.c"], 0.0), exit(2.0)]),
+            (3, vec![exec("/usr/bin/cc", &["-c", "b.c"], 0.0), exit(1.0)]),
+            (
+                4,
+                vec![
+                    exec("/usr/bin/cc", &["-o", "app", "a.o", "b.o"], 2.0),
+                    exit(4.0),
+                ],
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_report() {
+        let tree = tree();
+        let spans = tool_spans(&tree);
+        let slowest = slowest_compiles(&spans, 1);
+        assert_eq!(slowest.len(), 1);
+        assert_eq!(slowest[0].name, "cc a.c");
+        assert_eq!(compile_time_per_directory(&spans), vec![("/build", 3.0)]);
+        assert_eq!(parallelism(&spans, 0.0, 4.0, 4), vec![2.0, 1.0, 1.0, 1.0]);
+        assert_eq!(critical_path(&tree), vec![1, 4]);
+    }
+}
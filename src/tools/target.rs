@@ -0,0 +1,110 @@
+//! Keys telling apart the configurations a source is compiled in, such as
+//! host tools and target firmware or the 32 and 64-bit halves of a
+//! multilib build.
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::tools::driver;
+
+/// The target triple of a cross compiler named like `arm-none-eabi-gcc`.
+fn name_prefix(compiler: &str) -> Option<&str> {
+    lazy_static! {
+        static ref PREFIXED: Regex =
+            Regex::new(r"^(.+)-(gcc|g\+\+|cc|c\+\+|clang|clang\+\+)(-\d+(\.\d+){0,2})?$").unwrap();
+    }
+    let name = Path::new(compiler).file_name()?.to_str()?;
+    PREFIXED
+        .captures(name)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+}
+
+/// The configuration key of the compile `args` run as `compiler`: the
+/// target triple, from `-target`, `--target=` or the name of the
+/// compiler, or `host`, followed by the options selecting the ABI,
+/// architecture or sysroot, like `arm-none-eabi -march=armv7e-m` or
+/// `host -m32`. Later options override earlier ones.
+pub fn target_key(compiler: &str, args: &[String]) -> String {
+    let mut triple = name_prefix(compiler).map(|t| t.to_owned());
+    let mut bits = None;
+    let mut arch = None;
+    let mut abi = None;
+    let mut sysroot = None;
+    driver::for_each_arg(args, |a, operand| match a {
+        "-target" => triple = operand.map(|t| t.to_owned()),
+        "-m16" | "-m32" | "-mx32" | "-m64" => bits = Some(a.to_owned()),
+        "--sysroot" | "-isysroot" => sysroot = operand.map(|s| format!("--sysroot={}", s)),
+        _ => {
+            if let Some(t) = a.strip_prefix("--target=") {
+                triple = Some(t.to_owned());
+            } else if a.starts_with("-march=") {
+                arch = Some(a.to_owned());
+            } else if a.starts_with("-mabi=") {
+                abi = Some(a.to_owned());
+            } else if let Some(s) = a.strip_prefix("--sysroot=") {
+                sysroot = Some(format!("--sysroot={}", s));
+            }
+        }
+    });
+    let mut key = triple.unwrap_or_else(|| "host".to_owned());
+    for option in [bits, arch, abi, sysroot].iter().flatten() {
+        key.push(' ');
+        key.push_str(option);
+    }
+    key
+}
+
+/// Returns true if `key` is one of `selected`, or its triple is.
+pub fn is_selected(key: &str, selected: &[String]) -> bool {
+    let triple = key.split(' ').next().unwrap_or(key);
+    selected.iter().any(|s| s == key || s == triple)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_target_key() {
+        let args = strings(&["gcc", "-c", "a.c"]);
+        assert_eq!(target_key("/usr/bin/gcc-12", &args), "host");
+        assert_eq!(
+            target_key("/opt/bin/arm-none-eabi-gcc", &args),
+            "arm-none-eabi"
+        );
+        assert_eq!(
+            target_key("/usr/bin/x86_64-linux-gnu-g++-12", &args),
+            "x86_64-linux-gnu"
+        );
+
+        let args = strings(&["cc", "-m64", "-c", "-m32", "--sysroot", "/sdk", "a.c"]);
+        assert_eq!(target_key("/usr/bin/cc", &args), "host -m32 --sysroot=/sdk");
+
+        let args = strings(&[
+            "clang",
+            "-target",
+            "x86_64-pc-linux",
+            "--target=armv7m-none-eabi",
+            "-march=armv7e-m",
+            "-c",
+            "a.c",
+        ]);
+        assert_eq!(
+            target_key("/usr/bin/clang", &args),
+            "armv7m-none-eabi -march=armv7e-m"
+        );
+    }
+
+    #[test]
+    fn test_is_selected() {
+        let selected = strings(&["arm-none-eabi", "host -m32"]);
+        assert!(is_selected("arm-none-eabi -march=armv7e-m", &selected));
+        assert!(is_selected("host -m32", &selected));
+        assert!(!is_selected("host", &selected));
+    }
+}
@@ -0,0 +1,229 @@
+//! Timelines of tool invocations in the Chrome Trace Event format, which
+//! can be opened in `chrome://tracing` or Perfetto.
+use std::path::Path;
+
+use crate::error::{write_json, Result};
+use crate::process::ProcessTree;
+use crate::tools::ar::ArchiveCmd;
+use crate::tools::driver;
+use crate::tools::{CompilerAction, ToolKind};
+use crate::Exec;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct EventArgs {
+    directory: Option<String>,
+    command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retcode: Option<u8>,
+}
+
+/// A complete event (`"ph": "X"`); times are in microseconds.
+#[derive(Serialize, Debug, PartialEq)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+    args: EventArgs,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<TraceEvent>,
+    display_time_unit: &'static str,
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(path)
+}
+
+/// The category of the events for invocations of `t`, or None if they
+/// don't belong on the timeline.
+fn category(t: &ToolKind) -> Option<&'static str> {
+    match t {
+        ToolKind::CCompiler(CompilerAction::Compile)
+        | ToolKind::CXXCompiler(CompilerAction::Compile) => Some("compile"),
+        ToolKind::CCompiler(CompilerAction::Link)
+        | ToolKind::CXXCompiler(CompilerAction::Link)
+        | ToolKind::Linker => Some("link"),
+        ToolKind::Archiver => Some("archive"),
+        ToolKind::Assembler => Some("assemble"),
+        _ => None,
+    }
+}
+
+/// Names an invocation after the program and the file it works on: the
+/// source for compiles and the output otherwise.
+fn event_name(e: &Exec, cat: &str) -> String {
+    let target = match cat {
+        "compile" => driver::inputs(&e.args)
+            .first()
+            .map(|i| file_name(i.path).to_owned()),
+        "archive" => ArchiveCmd::from(e)
+            .first()
+            .map(|cmd| file_name(&cmd.archive).to_owned()),
+        _ => Some(file_name(driver::output(&e.args).unwrap_or("a.out")).to_owned()),
+    };
+    match target {
+        Some(target) => format!("{} {}", file_name(&e.path), target),
+        None => file_name(&e.path).to_owned(),
+    }
+}
+
+/// A compiler, linker, assembler or archiver run by a process of the build.
+#[derive(Debug, PartialEq)]
+pub struct Span<'a> {
+    pub pid: u32,
+    pub exec: &'a Exec,
+    /// Names the program and the file it works on, like `cc foo.c`.
+    pub name: String,
+    /// One of `compile`, `link`, `assemble` and `archive`.
+    pub cat: &'static str,
+    pub directory: Option<&'a str>,
+    pub start: f64,
+    /// None if the exit of the process wasn't recorded.
+    pub end: Option<f64>,
+    /// Set for the last program run by a process if it exited.
+    pub retcode: Option<u8>,
+}
+
+impl<'a> Span<'a> {
+    pub fn duration(&self) -> f64 {
+        self.end.map_or(0.0, |end| end - self.start)
+    }
+}
+
+/// The tool invocations of the build that have a timestamp, in the order
+/// they started.
+pub fn tool_spans(tree: &ProcessTree) -> Vec<Span<'_>> {
+    let mut spans = vec![];
+    for p in tree.processes() {
+        let last = p.execs.len();
+        for (i, (e, end)) in p.spans().enumerate() {
+            let cat = match category(&ToolKind::from(e)) {
+                Some(cat) => cat,
+                None => continue,
+            };
+            let start = match e.timestamp {
+                Some(ts) => ts,
+                None => continue,
+            };
+            spans.push(Span {
+                pid: p.pid,
+                exec: e,
+                name: event_name(e, cat),
+                cat,
+                directory: e
+                    .env
+                    .iter()
+                    .find(|(k, _v)| k == "PWD")
+                    .map(|(_k, v)| v.as_str()),
+                start,
+                end,
+                // the exit status belongs to the last program run
+                retcode: if i + 1 == last { p.retcode } else { None },
+            });
+        }
+    }
+    spans.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+    spans
+}
+
+/// One event per tool invocation, on the track of the process running it.
+/// Times are relative to the start of the build.
+fn trace_events(tree: &ProcessTree) -> Vec<TraceEvent> {
+    let start = tree.start().unwrap_or(0.0);
+    tool_spans(tree)
+        .into_iter()
+        .map(|s| TraceEvent {
+            ts: (s.start - start) * 1e6,
+            dur: s.duration() * 1e6,
+            name: s.name,
+            cat: s.cat,
+            ph: "X",
+            pid: s.pid,
+            tid: s.pid,
+            args: EventArgs {
+                directory: s.directory.map(|d| d.to_owned()),
+                command: s.exec.args.join(" "),
+                retcode: s.retcode,
+            },
+        })
+        .collect()
+}
+
+pub fn write_timeline(tree: &ProcessTree) -> Result<()> {
+    let trace = Trace {
+        trace_events: trace_events(tree),
+        display_time_unit: "ms",
+    };
+    write_json("trace.json", &trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Event;
+
+    fn exec(path: &str, args: &[&str], timestamp: f64) -> Event {
+        let mut e = Exec::mock(path, args);
+        e.env.push(("PWD".to_owned(), "/build".to_owned()));
+        e.timestamp = Some(timestamp);
+        Event::Exec(e)
+    }
+
+    fn exit(timestamp: f64) -> Event {
+        Event::Exit {
+            retcode: Some(0),
+            timestamp: Some(timestamp),
+        }
+    }
+
+    #[test]
+    fn test_trace_events() {
+        let tree = ProcessTree::from(vec![
+            (
+                1,
+                vec![
+                    exec("/usr/bin/make", &[], 10.0),
+                    Event::Fork {
+                        child: 2,
+                        timestamp: Some(10.5),
+                    },
+                    exit(14.0),
+                ],
+            ),
+            (
+                2,
+                vec![
+                    exec("/bin/sh", &["-c", "cc -c a.c"], 10.75),
+                    exec("/usr/bin/cc", &["-c", "src/a.c"], 11.0),
+                    exit(12.5),
+                ],
+            ),
+            (
+                3,
+                vec![exec("/usr/bin/ar", &["rcs", "liba.a", "a.o"], 13.0)],
+            ),
+        ]);
+        let events = trace_events(&tree);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].name, "cc a.c");
+        assert_eq!(events[0].cat, "compile");
+        assert_eq!(events[0].ts, 1e6);
+        assert_eq!(events[0].dur, 1.5e6);
+        assert_eq!(events[0].pid, 2);
+        assert_eq!(events[0].args.retcode, Some(0));
+        assert_eq!(events[0].args.directory.as_deref(), Some("/build"));
+        assert_eq!(events[1].name, "ar liba.a");
+        // the archiver's exit wasn't recorded
+        assert_eq!(events[1].dur, 0.0);
+    }
+}
@@ -0,0 +1,201 @@
+//! Re-running the entries of a compilation database to catch stale or
+//! wrongly filtered commands.
+use std::env::temp_dir;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use tempfile::tempdir;
+
+use crate::error::{Error, Result};
+use crate::tools::ccmd::CompileCmd;
+use crate::tools::driver;
+use crate::tools::ToolKind;
+use crate::Exec;
+
+/// How entries are re-run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyMode {
+    /// Only parse and type check with `-fsyntax-only`.
+    SyntaxOnly,
+    /// Compile to an object in a scratch directory.
+    Compile,
+}
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "syntax-only" => Ok(VerifyMode::SyntaxOnly),
+            "compile" => Ok(VerifyMode::Compile),
+            _ => Err(format!("unknown verification mode: {}", s)),
+        }
+    }
+}
+
+impl VerifyMode {
+    pub const NAMES: &'static [&'static str] = &["syntax-only", "compile"];
+}
+
+/// An entry that failed to compile.
+#[derive(Debug)]
+pub struct Failure {
+    pub index: usize,
+    pub directory: String,
+    pub file: String,
+    pub output: String,
+}
+
+/// Returns true if `args` run the assembler rather than a compiler driver.
+fn is_assembler(args: &[String]) -> bool {
+    let e = Exec {
+        pid: 0,
+        path: args.first().cloned().unwrap_or_default(),
+        args: args.to_vec(),
+        env: vec![],
+        retcode: 0,
+        timestamp: None,
+    };
+    !e.path.is_empty() && ToolKind::from(&e) == ToolKind::Assembler
+}
+
+/// Rewrites `args` so re-running them leaves the build tree untouched:
+/// outputs and dependency files are dropped, and either `-fsyntax-only` is
+/// added or the object is written to `scratch`. The assembler has no
+/// syntax-only mode, so its entries are always assembled to `scratch`.
+fn verify_args(args: &[String], mode: VerifyMode, scratch: &Path) -> Vec<String> {
+    let syntax_only = mode == VerifyMode::SyntaxOnly && !is_assembler(args);
+    let mut res = vec![];
+    let mut iter = args.iter();
+    res.extend(iter.next().cloned());
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" | "-MF" | "-MT" | "-MQ" | "--MD" => {
+                iter.next();
+            }
+            "-MD" | "-MMD" | "-MP" | "-MG" => {}
+            "-c" | "-S" | "-E" if syntax_only => {}
+            a if a.starts_with("-Wp,-M") || driver::attached_output(a).is_some() => {}
+            _ => res.push(arg.clone()),
+        }
+    }
+    if syntax_only {
+        res.push("-fsyntax-only".to_owned());
+    } else {
+        res.push("-o".to_owned());
+        res.push(scratch.to_string_lossy().into_owned());
+    }
+    res
+}
+
+pub fn read_compile_commands(path: &Path) -> Result<Vec<CompileCmd>> {
+    let contents = read_to_string(path).map_err(|error| Error::Input {
+        path: path.to_owned(),
+        error,
+    })?;
+    let mut cmds: Vec<CompileCmd> = serde_json::from_str(&contents).map_err(|e| Error::Parse {
+        path: path.to_owned(),
+        message: e.to_string(),
+    })?;
+    for cmd in &mut cmds {
+        cmd.normalize();
+    }
+    Ok(cmds)
+}
+
+/// Re-runs `cmds` on `jobs` threads and returns the entries that failed,
+/// in database order.
+pub fn verify(cmds: &[CompileCmd], mode: VerifyMode, jobs: usize) -> Result<Vec<Failure>> {
+    let scratch_error = |error| Error::Output {
+        path: temp_dir(),
+        error,
+    };
+    let scratch = tempdir().map_err(scratch_error)?;
+    let next = AtomicUsize::new(0);
+    let failures = Mutex::new(vec![]);
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let cmd = match cmds.get(index) {
+                    Some(cmd) => cmd,
+                    None => break,
+                };
+                let object = scratch.path().join(format!("{}.o", index));
+                let args = verify_args(cmd.arguments(), mode, &object);
+                let output = match args.split_first() {
+                    Some((program, args)) => Command::new(program)
+                        .args(args)
+                        .current_dir(cmd.directory())
+                        .envs(cmd.environment())
+                        .output()
+                        .map(|o| {
+                            (
+                                o.status.success(),
+                                String::from_utf8_lossy(&o.stderr).into_owned(),
+                            )
+                        })
+                        .unwrap_or_else(|e| (false, format!("couldn't run {}: {}", program, e))),
+                    None => (false, "empty command".to_owned()),
+                };
+                if let (false, output) = output {
+                    failures.lock().unwrap().push(Failure {
+                        index,
+                        directory: cmd.directory().to_owned(),
+                        file: cmd.file().to_owned(),
+                        output,
+                    });
+                }
+            });
+        }
+    });
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by_key(|f| f.index);
+    scratch.close().map_err(scratch_error)?;
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_verify_args() {
+        let args = strings(&[
+            "cc",
+            "-c",
+            "-MD",
+            "-MF",
+            "a.d",
+            "-Wp,-MD,.a.d",
+            "-Iinc",
+            "-o",
+            "a.o",
+            "a.c",
+        ]);
+        let scratch = Path::new("/tmp/x/0.o");
+        assert_eq!(
+            verify_args(&args, VerifyMode::SyntaxOnly, scratch),
+            strings(&["cc", "-Iinc", "a.c", "-fsyntax-only"])
+        );
+        assert_eq!(
+            verify_args(&args, VerifyMode::Compile, scratch),
+            strings(&["cc", "-c", "-Iinc", "a.c", "-o", "/tmp/x/0.o"])
+        );
+
+        let args = strings(&["as", "--MD", "a.d", "-o", "a.o", "a.s"]);
+        assert_eq!(
+            verify_args(&args, VerifyMode::SyntaxOnly, scratch),
+            strings(&["as", "a.s", "-o", "/tmp/x/0.o"])
+        );
+    }
+}
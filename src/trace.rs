@@ -0,0 +1,466 @@
+//! Running a build under strace and reading back its logs.
+use std::collections::BTreeMap;
+use std::env::temp_dir;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread::sleep;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::error::{Error, Result};
+use crate::parser::{self, Event};
+use crate::process::ProcessTree;
+use crate::sink::{Dispatcher, EventSink, LogEntry};
+
+/// How long `Tracer::run` waits for strace to write more when it has read
+/// everything.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+fn locate_strace() -> Result<String> {
+    // get path to strace
+    let which_output = Command::new("which")
+        .arg("strace")
+        .output()
+        .map_err(|e| Error::StraceNotFound(format!("couldn't run which: {}", e)))?;
+    if !which_output.status.success() {
+        return Err(Error::StraceNotFound("couldn't find strace".to_owned()));
+    }
+    let strace_path = String::from_utf8_lossy(&which_output.stdout)
+        .trim_end()
+        .to_owned();
+
+    // check that strace -V produces sane output
+    let sane = Command::new(&strace_path)
+        .arg("-V")
+        .output()
+        .map(|o| o.status.success() && o.stdout.starts_with(b"strace -- version"))
+        .unwrap_or(false);
+    if !sane {
+        return Err(Error::StraceNotFound(format!(
+            "{} doesn't look like strace",
+            strace_path
+        )));
+    }
+
+    Ok(strace_path)
+}
+
+/// The pid of the process whose strace log is `file`, named
+/// `$output_file.$pid` by `strace -ff`.
+fn log_pid(file: &Path) -> Result<u32> {
+    file.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| ext.parse::<u32>().ok())
+        .ok_or_else(|| Error::Parse {
+            path: file.to_owned(),
+            message: "not named after a process id".to_owned(),
+        })
+}
+
+/// Parses a line of a log; None for lines about calls that aren't
+/// reported, such as thread creation.
+fn log_entry(line: String) -> Option<LogEntry> {
+    match parser::parseln(&line) {
+        Ok(event) => event.map(Ok),
+        Err(_) => Some(Err(line)),
+    }
+}
+
+/// Reads the strace log `file` of a process and returns the pid and the
+/// entries of the log.
+fn read_log(file: &Path) -> Result<(u32, Vec<LogEntry>)> {
+    let pid = log_pid(file)?;
+    let input_error = |error| Error::Input {
+        path: file.to_owned(),
+        error,
+    };
+    let f = File::open(file).map_err(input_error)?;
+    let buf = BufReader::new(&f);
+    let mut res = vec![];
+    for l in buf.lines() {
+        res.extend(log_entry(l.map_err(input_error)?));
+    }
+
+    Ok((pid, res))
+}
+
+/// The strace log of a process, read while strace is still writing it.
+struct LogReader {
+    pid: u32,
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// The start of a line strace hasn't finished writing.
+    partial: Vec<u8>,
+}
+
+impl LogReader {
+    fn open(path: PathBuf) -> Result<Self> {
+        let pid = log_pid(&path)?;
+        let f = File::open(&path).map_err(|error| Error::Input {
+            path: path.clone(),
+            error,
+        })?;
+        Ok(LogReader {
+            pid,
+            path,
+            reader: BufReader::new(f),
+            partial: vec![],
+        })
+    }
+
+    /// Reads the entries of the lines completed since the last call.
+    fn read(&mut self) -> Result<Vec<LogEntry>> {
+        let mut res = vec![];
+        loop {
+            let n = self
+                .reader
+                .read_until(b'\n', &mut self.partial)
+                .map_err(|error| Error::Input {
+                    path: self.path.clone(),
+                    error,
+                })?;
+            if n == 0 || !self.partial.ends_with(b"\n") {
+                return Ok(res);
+            }
+            self.partial.pop();
+            let line = String::from_utf8_lossy(&self.partial).into_owned();
+            self.partial.clear();
+            res.extend(log_entry(line));
+        }
+    }
+}
+
+/// Splits the entries of a log into its events and the `execve` lines that
+/// couldn't be parsed; other malformed lines are dropped.
+fn split_log(log: Vec<LogEntry>) -> (Vec<Event>, Vec<String>) {
+    let mut events = vec![];
+    let mut unparsed = vec![];
+    for entry in log {
+        match entry {
+            Ok(event) => events.push(event),
+            Err(line) if line.contains("execve(") => unparsed.push(line),
+            Err(_) => {}
+        }
+    }
+    (events, unparsed)
+}
+
+/// Reads the events of the process whose strace log is `file`, named
+/// `$output_file.$pid` by `strace -ff`. Malformed lines are dropped.
+pub fn process_output_file(file: &Path) -> Result<(u32, Vec<Event>)> {
+    let (pid, log) = read_log(file)?;
+    Ok((pid, split_log(log).0))
+}
+
+/// The strace logs in `tmp_dir`, which strace writes nothing else to.
+fn list_logs(tmp_dir: &Path) -> Result<Vec<PathBuf>> {
+    let input_error = |error| Error::Input {
+        path: tmp_dir.to_owned(),
+        error,
+    };
+    let mut tmp_files = vec![];
+    for entry in tmp_dir.read_dir().map_err(input_error)? {
+        let entry = entry.map_err(input_error)?;
+        if entry.file_type().map_err(input_error)?.is_file() {
+            tmp_files.push(entry.path());
+        }
+    }
+    Ok(tmp_files)
+}
+
+/// Kills the build run by `strace`, which leads its process group.
+/// Processes that left the group, such as daemons, keep running.
+fn kill_build(strace: &mut Child) -> Result<()> {
+    let killed = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", strace.id())])
+        .status()
+        .is_ok_and(|s| s.success());
+    if !killed {
+        strace.kill().map_err(Error::Strace)?;
+    }
+    strace.wait().map_err(Error::Strace)?;
+    Ok(())
+}
+
+/// Runs a build command under strace and collects the processes it ran.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    cmd: Vec<OsString>,
+    strace: Option<PathBuf>,
+    current_dir: Option<PathBuf>,
+    env: Vec<(OsString, OsString)>,
+    opens: bool,
+    renames: bool,
+}
+
+impl Tracer {
+    /// Traces `cmd`, the program to run followed by its arguments.
+    pub fn new<I, S>(cmd: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        Tracer {
+            cmd: cmd.into_iter().map(|a| a.into()).collect(),
+            strace: None,
+            current_dir: None,
+            env: vec![],
+            opens: false,
+            renames: false,
+        }
+    }
+
+    /// Runs this strace rather than the one in `PATH`.
+    pub fn strace<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.strace = Some(path.into());
+        self
+    }
+
+    /// Runs the build in `dir` rather than the current directory.
+    pub fn current_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the environment variable `key` for the build.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, value: V) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Also traces the files opened or looked up with `stat` by the build,
+    /// which slows it down.
+    pub fn opens(mut self, opens: bool) -> Self {
+        self.opens = opens;
+        self
+    }
+
+    /// Also traces the files renamed by the build.
+    pub fn renames(mut self, renames: bool) -> Self {
+        self.renames = renames;
+        self
+    }
+
+    /// The strace command running the build, logging each process to
+    /// `$output_file.$pid`.
+    fn command(&self, output_file: &Path) -> Result<Command> {
+        // only trace execve and process creation/exit, and opens and
+        // renames if asked to
+        let mut syscalls = "trace=process".to_owned();
+        if self.opens {
+            syscalls.push_str(",open,openat,stat,lstat,newfstatat,statx");
+        }
+        if self.renames {
+            syscalls.push_str(",rename,renameat,renameat2");
+        }
+        let strace_args = [
+            "-ff", // follow forks
+            "-e", &syscalls, // only trace these calls
+            "-s", "8192", // set max string length
+            "-v",   // request unabridged output
+            "-ttt", // prefix lines with a timestamp
+        ];
+        let strace_path = match &self.strace {
+            Some(path) => path.clone(),
+            None => PathBuf::from(locate_strace()?),
+        };
+
+        let mut strace = Command::new(strace_path);
+        strace
+            .arg("-o")
+            .arg(output_file)
+            .args(strace_args)
+            .args(&self.cmd)
+            .envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.current_dir {
+            strace.current_dir(dir);
+        }
+        Ok(strace)
+    }
+
+    /// Runs the build and returns the strace logs of its processes.
+    fn run_strace(&self, output_file: &Path) -> Result<Vec<PathBuf>> {
+        let status = self.command(output_file)?.status().map_err(Error::Strace)?;
+        if !status.success() {
+            return Err(Error::BuildFailed(status.code()));
+        }
+        list_logs(output_file.parent().unwrap())
+    }
+
+    /// Runs the build, tracing it into a temporary directory, and reads each
+    /// log with `read`.
+    fn read_logs<T>(&self, read: fn(&Path) -> Result<T>) -> Result<Vec<T>> {
+        // Create a directory inside of `std::env::temp_dir()`
+        let tmp_error = |error| Error::Output {
+            path: temp_dir(),
+            error,
+        };
+        let tmp_dir = tempdir().map_err(tmp_error)?;
+        let strace_outfile = tmp_dir.path().join("rstrace.out");
+
+        let res = self
+            .run_strace(&strace_outfile)?
+            .iter()
+            .map(|file| read(file))
+            .collect::<Result<Vec<_>>>()?;
+
+        // `tmp_dir` goes out of scope, the directory will be deleted here.
+        tmp_dir.close().map_err(tmp_error)?;
+        Ok(res)
+    }
+
+    /// Runs the build and returns its processes, along with the `execve`
+    /// lines that couldn't be parsed.
+    pub fn trace(&self) -> Result<ProcessTree> {
+        let mut logs = vec![];
+        let mut unparsed = vec![];
+        for (pid, log) in self.read_logs(read_log)? {
+            let (events, lines) = split_log(log);
+            logs.push((pid, events));
+            unparsed.extend(lines.into_iter().map(|l| (pid, l)));
+        }
+        let mut tree = ProcessTree::from(logs);
+        unparsed.sort();
+        tree.unparsed = unparsed;
+        Ok(tree)
+    }
+
+    /// Runs the build and delivers its events to `sinks` as strace writes
+    /// them, ordered by time among those read together, until every sink
+    /// has stopped. The build is then killed, along with the processes it
+    /// started, and isn't reported as failed.
+    pub fn run(&self, sinks: &mut [&mut dyn EventSink]) -> Result<()> {
+        let tmp_error = |error| Error::Output {
+            path: temp_dir(),
+            error,
+        };
+        let tmp_dir = tempdir().map_err(tmp_error)?;
+        let strace_outfile = tmp_dir.path().join("rstrace.out");
+        let mut strace = self
+            .command(&strace_outfile)?
+            .process_group(0)
+            .spawn()
+            .map_err(Error::Strace)?;
+
+        let mut dispatcher = Dispatcher::new(sinks);
+        let mut logs = BTreeMap::new();
+        let status = loop {
+            // read the logs once more after the build exits
+            let status = strace.try_wait().map_err(Error::Strace)?;
+            for path in list_logs(tmp_dir.path())? {
+                if !logs.contains_key(&path) {
+                    logs.insert(path.clone(), LogReader::open(path)?);
+                }
+            }
+            let mut entries = vec![];
+            for log in logs.values_mut() {
+                let log_entries = log.read()?;
+                if !log_entries.is_empty() {
+                    entries.push((log.pid, log_entries));
+                }
+            }
+            let idle = entries.is_empty();
+            dispatcher.dispatch(entries);
+
+            if status.is_some() {
+                break status;
+            } else if dispatcher.stopped() {
+                kill_build(&mut strace)?;
+                break None;
+            } else if idle {
+                sleep(POLL_INTERVAL);
+            }
+        };
+
+        tmp_dir.close().map_err(tmp_error)?;
+        match status {
+            Some(status) if !status.success() => Err(Error::BuildFailed(status.code())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Exec;
+    use std::ops::ControlFlow;
+    use std::time::Instant;
+
+    #[test]
+    fn test_process_output_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("rstrace.out.42");
+        std::fs::write(
+            &log,
+            "1.5 --- SIGCHLD {si_signo=SIGCHLD} ---\n1.5 execve(\"/usr/bin/cc\n1.5 +++ exited with 0 +++\n",
+        )
+        .unwrap();
+        let (pid, log) = read_log(&log).unwrap();
+        assert_eq!(pid, 42);
+        let (events, unparsed) = split_log(log);
+        assert_eq!(events.len(), 1);
+        assert_eq!(unparsed, vec!["1.5 execve(\"/usr/bin/cc"]);
+    }
+
+    /// Writes a script standing in for strace, which runs `body` with the
+    /// log of a process in `$log`.
+    fn fake_strace(dir: &Path, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join("strace");
+        std::fs::write(&path, format!("#!/bin/sh\nlog=\"$2.100\"\n{}", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        // the build only goes on once the sink has seen its first exec
+        let strace = fake_strace(
+            dir.path(),
+            r#"echo '1.0 execve("/usr/bin/make", ["make"], []) = 0' > "$log"
+for i in $(seq 100); do [ -e "$SEEN" ] && break; sleep 0.1; done
+[ -e "$SEEN" ] || exit 1
+echo '2.0 execve("/usr/bin/cc", ["cc"], []) = 0' >> "$log"
+echo '3.0 +++ exited with 0 +++' >> "$log"
+"#,
+        );
+        let seen = dir.path().join("seen");
+        let mut execs = vec![];
+        let mut on_exec = |e: &Exec| {
+            std::fs::write(&seen, "").unwrap();
+            execs.push(e.path.clone());
+            ControlFlow::Continue(())
+        };
+        Tracer::new(["make"])
+            .strace(strace)
+            .env("SEEN", seen.clone())
+            .run(&mut [&mut on_exec])
+            .unwrap();
+        assert_eq!(execs, vec!["/usr/bin/make", "/usr/bin/cc"]);
+    }
+
+    #[test]
+    fn test_run_stops_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let strace = fake_strace(
+            dir.path(),
+            r#"echo '1.0 execve("/usr/bin/make", ["make"], []) = 0' > "$log"
+sleep 30
+"#,
+        );
+        let mut on_exec = |_: &Exec| ControlFlow::Break(());
+        let start = Instant::now();
+        Tracer::new(["make"])
+            .strace(strace)
+            .run(&mut [&mut on_exec])
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}